@@ -0,0 +1,89 @@
+use image::{Rgb, Rgb32FImage};
+use rayon::prelude::*;
+
+use crate::maths::Color;
+
+// running sum of linear radiance per pixel, rows stored top to bottom like the output image
+pub struct Accumulator {
+    pub width: u32,
+    pub height: u32,
+    pixels: Vec<Color>,
+    samples: u32,
+}
+
+impl Accumulator {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![Color::new(0.0, 0.0, 0.0); (width * height) as usize],
+            samples: 0,
+        }
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    // adds one sample to every pixel, `sample` gets the pixel position with y pointing up
+    pub fn add_pass<F>(&mut self, sample: F, row_done: impl Fn() + Sync)
+    where
+        F: Fn(u32, u32) -> Color + Sync,
+    {
+        let width = self.width;
+        let height = self.height;
+        self.pixels
+            .par_chunks_mut(width as usize)
+            .enumerate()
+            .for_each(|(row, pixels)| {
+                let y = height - row as u32 - 1;
+                for (x, pixel) in pixels.iter_mut().enumerate() {
+                    *pixel = *pixel + sample(x as u32, y);
+                }
+                row_done();
+            });
+        self.samples += 1;
+    }
+
+    pub fn average(&self) -> impl Iterator<Item = Color> + '_ {
+        let samples = self.samples.max(1) as f64;
+        self.pixels.iter().map(move |pixel| *pixel / samples)
+    }
+
+    pub fn to_image(&self) -> Rgb32FImage {
+        let mut img = Rgb32FImage::new(self.width, self.height);
+        for (pixel, color) in img.pixels_mut().zip(self.average()) {
+            *pixel = Rgb([color.x as f32, color.y as f32, color.z as f32]);
+        }
+        img
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Accumulator;
+    use crate::maths::Color;
+
+    #[test]
+    fn passes_are_averaged_per_pixel() {
+        let mut acc = Accumulator::new(3, 2);
+        assert!(acc.average().all(|c| c == Color::new(0.0, 0.0, 0.0)));
+
+        // pass k gives pixel (x, y) the value x + 10 y + k
+        for pass in 0..4 {
+            acc.add_pass(
+                |x, y| Color::new((x + 10 * y + pass) as f64, 1.0, 0.0),
+                || {},
+            );
+        }
+        assert_eq!(acc.samples(), 4);
+
+        let img = acc.to_image();
+        for (x, y, pixel) in img.enumerate_pixels() {
+            // image rows run top to bottom, the sample positions bottom to top
+            let expected = x as f64 + 10.0 * (1 - y) as f64 + 1.5;
+            assert!((pixel[0] as f64 - expected).abs() < 1e-6);
+            assert_eq!(pixel[1], 1.0);
+        }
+    }
+}
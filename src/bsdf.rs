@@ -1,32 +1,94 @@
-use crate::maths::{Vec3, Color, HDR};
+use crate::maths::{Color, Vec3, HDR};
 
 use core::f64::consts::PI;
-use std::f64::INFINITY;
 
+#[allow(clippy::upper_case_acronyms)]
 pub struct BSDF {}
 
 impl BSDF {
+    // metallic workflow: `reflectivity` blends from a dielectric with 4% specular
+    // reflectance to a metal tinted by `color`, the result does not include the cosine term
     pub fn cook_torrance_brdf(
-        kd: f64, 
-        f0: f64, 
-        roughness: f64, 
-        wo: Vec3, 
-        wi: Vec3, 
-        normal: Vec3, 
-        color: Color, 
+        roughness: f64,
+        reflectivity: f64,
+        wo: Vec3,
+        wi: Vec3,
+        normal: Vec3,
+        color: Color,
     ) -> HDR {
-        let diffuse = kd * 1.0 / PI;
-        let v = wo.normalize();
         let n = normal;
+        let v = wo.normalize();
         let l = wi.normalize();
+        let n_dot_v = n * v;
+        let n_dot_l = n * l;
+        if n_dot_v <= 0.0 || n_dot_l <= 0.0 {
+            return HDR::new(0.0, 0.0, 0.0);
+        }
         let h = (l + v).normalize();
-        let alpha = roughness;
-        let k = alpha.powf(2.0) / 2.0;
-        let D = alpha.powf(2.0) / PI * ((n * h).powf(2.0) * (alpha.powf(2.0) - 1.0) + 1.0).powf(2.0);
-        let G = (n * v) / (n * v * (1.0 - k) + k);
-        let F = f0 + (1.0 - f0) * 1.0 - (h * v).powf(5.0);
-        let specular = D * G * F / (4.0 * (v * n) * (l * n));
-        let fr = diffuse + specular;
-        return color * fr * (n * l).clamp(0.0, INFINITY);
+        let alpha = Self::alpha(roughness);
+
+        let f0 = Color::new(0.04, 0.04, 0.04) * (1.0 - reflectivity) + color * reflectivity;
+        let f = Self::fresnel_schlick(f0, h * v);
+        let d = Self::ggx_distribution(n * h, alpha);
+        let g = Self::smith_g1(n_dot_v, alpha) * Self::smith_g1(n_dot_l, alpha);
+
+        let specular = f * (d * g / (4.0 * n_dot_v * n_dot_l));
+        let diffuse = color.mix(Color::new(1.0, 1.0, 1.0) - f) * ((1.0 - reflectivity) / PI);
+        diffuse + specular
+    }
+
+    // perceptual roughness to GGX alpha, kept away from zero so the lobe stays finite
+    pub fn alpha(roughness: f64) -> f64 {
+        (roughness * roughness).max(1e-3)
+    }
+
+    pub fn ggx_distribution(n_dot_h: f64, alpha: f64) -> f64 {
+        if n_dot_h <= 0.0 {
+            return 0.0;
+        }
+        let a2 = alpha * alpha;
+        let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+        a2 / (PI * d * d)
+    }
+
+    pub fn smith_g1(n_dot_v: f64, alpha: f64) -> f64 {
+        let a2 = alpha * alpha;
+        2.0 * n_dot_v / (n_dot_v + (a2 + (1.0 - a2) * n_dot_v * n_dot_v).sqrt())
+    }
+
+    // unpolarized Fresnel reflectance of a dielectric interface, `eta` is the transmitted over
+    // the incident index of refraction and `cos_i` is measured on the incident side
+    pub fn fresnel_dielectric(cos_i: f64, eta: f64) -> f64 {
+        let cos_i = cos_i.clamp(0.0, 1.0);
+        let sin2_t = (1.0 - cos_i * cos_i) / (eta * eta);
+        if sin2_t >= 1.0 {
+            return 1.0;
+        }
+        let cos_t = (1.0 - sin2_t).sqrt();
+        let r_parallel = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+        let r_perpendicular = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+        (r_parallel * r_parallel + r_perpendicular * r_perpendicular) / 2.0
+    }
+
+    pub fn fresnel_schlick(f0: Color, cos_theta: f64) -> Color {
+        let k = (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5);
+        f0 + (Color::new(1.0, 1.0, 1.0) - f0) * k
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BSDF;
+
+    #[test]
+    fn fresnel_dielectric_limits() {
+        // normal incidence on glass reflects ((n - 1) / (n + 1))^2
+        assert!((BSDF::fresnel_dielectric(1.0, 1.5) - 0.04).abs() < 1e-12);
+        // grazing incidence reflects everything
+        assert!((BSDF::fresnel_dielectric(0.0, 1.5) - 1.0).abs() < 1e-12);
+        // past the critical angle of about 41.8 degrees inside glass
+        let cos_i = 45f64.to_radians().cos();
+        assert_eq!(BSDF::fresnel_dielectric(cos_i, 1.0 / 1.5), 1.0);
+        assert!(BSDF::fresnel_dielectric(0.8, 1.0 / 1.5) < 1.0);
     }
 }
@@ -0,0 +1,171 @@
+use std::sync::Arc;
+
+use crate::{
+    aabb::Aabb,
+    hit::{HitRecord, Hittable, HittableList},
+    ray::Ray,
+};
+
+pub struct BvhNode {
+    left: Arc<dyn Hittable>,
+    right: Arc<dyn Hittable>,
+    bbox: Aabb,
+}
+
+impl BvhNode {
+    pub fn new(objects: &[Arc<dyn Hittable>]) -> Self {
+        match objects.len() {
+            0 => Self::from_children(Arc::new(HittableList::new()), Arc::new(HittableList::new())),
+            1 => Self::from_children(objects[0].clone(), Arc::new(HittableList::new())),
+            _ => {
+                let mut objects: Vec<(Aabb, Arc<dyn Hittable>)> = objects
+                    .iter()
+                    .map(|object| (object.bounding_box(), object.clone()))
+                    .collect();
+                Self::split(&mut objects)
+            }
+        }
+    }
+
+    fn from_children(left: Arc<dyn Hittable>, right: Arc<dyn Hittable>) -> Self {
+        let bbox = left.bounding_box().union(&right.bounding_box());
+        Self { left, right, bbox }
+    }
+
+    fn build(objects: &mut [(Aabb, Arc<dyn Hittable>)]) -> Arc<dyn Hittable> {
+        if objects.len() == 1 {
+            return objects[0].1.clone();
+        }
+        Arc::new(Self::split(objects))
+    }
+
+    // sort the objects along the longest axis of their centroids and cut where the
+    // surface area heuristic is cheapest
+    fn split(objects: &mut [(Aabb, Arc<dyn Hittable>)]) -> Self {
+        let centroid_bounds = objects.iter().fold(Aabb::empty(), |bbox, (b, _)| {
+            let c = b.centroid();
+            bbox.union(&Aabb { min: c, max: c })
+        });
+        let axis = centroid_bounds.longest_axis();
+        objects.sort_by(|(a, _), (b, _)| a.centroid()[axis].total_cmp(&b.centroid()[axis]));
+
+        let n = objects.len();
+        let mut right_areas = vec![0.0; n];
+        let mut bbox = Aabb::empty();
+        for i in (1..n).rev() {
+            bbox = bbox.union(&objects[i].0);
+            right_areas[i] = bbox.surface_area();
+        }
+
+        let mut mid = n / 2;
+        let mut best_cost = f64::INFINITY;
+        let mut bbox = Aabb::empty();
+        for i in 1..n {
+            bbox = bbox.union(&objects[i - 1].0);
+            let cost = bbox.surface_area() * i as f64 + right_areas[i] * (n - i) as f64;
+            if cost < best_cost {
+                best_cost = cost;
+                mid = i;
+            }
+        }
+
+        let (left, right) = objects.split_at_mut(mid);
+        Self::from_children(Self::build(left), Self::build(right))
+    }
+}
+
+impl From<&HittableList> for BvhNode {
+    fn from(value: &HittableList) -> Self {
+        Self::new(value.objects())
+    }
+}
+
+impl Hittable for BvhNode {
+    fn get_hit_record(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        if !self.bbox.hit(ray, t_min, t_max) {
+            return None;
+        }
+        let left = self.left.get_hit_record(ray, t_min, t_max);
+        let closest = left.as_ref().map_or(t_max, |rec| rec.t);
+        let right = self.right.get_hit_record(ray, t_min, closest);
+        right.or(left)
+    }
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::BvhNode;
+    use crate::{
+        hit::{Hittable, HittableList},
+        material::{Diffuse, Material},
+        maths::{Color, Vec3},
+        objects::{Plane, Sphere},
+        ray::Ray,
+    };
+
+    fn rand_vec(rng: &mut StdRng, range: f64) -> Vec3 {
+        Vec3::new(
+            rng.gen_range(-range..range),
+            rng.gen_range(-range..range),
+            rng.gen_range(-range..range),
+        )
+    }
+
+    #[test]
+    fn bvh_matches_linear_traversal() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let material: Arc<dyn Material> = Arc::new(Diffuse::new(Color::new(0.5, 0.5, 0.5)));
+        let mut world = HittableList::new();
+        for _ in 0..500 {
+            world.add(Arc::new(Sphere::new(
+                rand_vec(&mut rng, 20.0),
+                rng.gen_range(0.1..2.0),
+                material.clone(),
+            )));
+        }
+        // moving spheres only stay findable if their boxes cover the whole motion
+        for _ in 0..100 {
+            let center = rand_vec(&mut rng, 20.0);
+            world.add(Arc::new(Sphere::moving(
+                center,
+                center + rand_vec(&mut rng, 5.0),
+                rng.gen_range(0.1..2.0),
+                material.clone(),
+            )));
+        }
+        for _ in 0..100 {
+            world.add(Arc::new(Plane::new(
+                rand_vec(&mut rng, 20.0),
+                rand_vec(&mut rng, 3.0),
+                rand_vec(&mut rng, 3.0),
+                material.clone(),
+            )));
+        }
+        let bvh = BvhNode::from(&world);
+
+        let mut hits = 0;
+        for _ in 0..5000 {
+            let ray = Ray::new(rand_vec(&mut rng, 25.0), rand_vec(&mut rng, 1.0))
+                .with_time(rng.gen_range(0.0..1.0));
+            let linear = world.get_hit_record(&ray, 0.0001, f64::INFINITY);
+            let accelerated = bvh.get_hit_record(&ray, 0.0001, f64::INFINITY);
+            match (linear, accelerated) {
+                (Some(a), Some(b)) => {
+                    assert_eq!(a.t, b.t);
+                    assert!(std::ptr::addr_eq(a.obj, b.obj));
+                    hits += 1;
+                }
+                (None, None) => {}
+                _ => panic!("bvh and linear traversal disagree"),
+            }
+        }
+        assert!(hits > 0);
+    }
+}
@@ -1,53 +1,453 @@
+use core::f64::consts::PI;
+
+use serde::Deserialize;
+
 use crate::{
+    error::{FieldError, Validate},
     maths::{Point3, Vec3},
+    random::rand_f64,
     ray::Ray,
+    sampling,
 };
 
+// height of a full frame sensor in scene units, taken to be meters, to turn f-stops into
+// aperture sizes
+const SENSOR_HEIGHT: f64 = 0.024;
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum Projection {
+    #[default]
+    Perspective,
+    // parallel rays along the view direction, `ortho_height` tall
+    Orthographic,
+    // equidistant fisheye, the angle from the view direction grows linearly with the distance
+    // from the image center and `vfov` spans the height of the image circle
+    Fisheye,
+    // full 360 x 180 degree panorama in the layout environment maps use
+    Equirectangular,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct SerializationCamera {
+    projection: Projection,
+    lookfrom: (f64, f64, f64),
+    lookat: (f64, f64, f64),
+    vup: (f64, f64, f64),
+    // vertical field of view in degrees
+    vfov: f64,
+    // height of the view in scene units for the orthographic projection
+    ortho_height: f64,
+    // lens radius, zero keeps everything in focus
+    aperture: Option<f64>,
+    // f-number, an alternative to `aperture` using the focal length implied by `vfov`
+    fstop: Option<f64>,
+    // distance to the plane in focus, defaults to the distance to `lookat`
+    focus_distance: Option<f64>,
+    // number of aperture blades shaping the bokeh, zero for a round aperture
+    blades: u32,
+    // part of the [0, 1] motion interval of the objects the exposure covers
+    shutter_open: f64,
+    shutter_close: f64,
+}
+
+// the defaults frame the scene the way the fixed camera used to, from the origin down -z
+impl Default for SerializationCamera {
+    fn default() -> Self {
+        Self {
+            projection: Projection::Perspective,
+            lookfrom: (0.0, 0.0, 0.0),
+            lookat: (0.0, 0.0, -1.0),
+            vup: (0.0, 1.0, 0.0),
+            vfov: 90.0,
+            ortho_height: 2.0,
+            aperture: None,
+            fstop: None,
+            focus_distance: None,
+            blades: 0,
+            shutter_open: 0.0,
+            shutter_close: 0.0,
+        }
+    }
+}
+
+impl SerializationCamera {
+    fn lens_radius(&self) -> f64 {
+        match (self.aperture, self.fstop) {
+            (Some(aperture), _) => aperture,
+            (None, Some(fstop)) => {
+                let focal_length = SENSOR_HEIGHT / 2.0 / (self.vfov.to_radians() / 2.0).tan();
+                focal_length / fstop / 2.0
+            }
+            (None, None) => 0.0,
+        }
+    }
+
+    fn focus_distance(&self) -> f64 {
+        self.focus_distance
+            .unwrap_or_else(|| (Vec3::from(self.lookat) - self.lookfrom.into()).length())
+    }
+}
+
+impl Validate for SerializationCamera {
+    fn validate(&self) -> Result<(), FieldError> {
+        let view = Vec3::from(self.lookat) - self.lookfrom.into();
+        if !view.length_squared().is_normal() {
+            return Err(FieldError::new(
+                "lookat",
+                "lookat must differ from lookfrom",
+            ));
+        }
+        if !view.cross(&self.vup.into()).length_squared().is_normal() {
+            return Err(FieldError::new(
+                "vup",
+                "vup must not be zero or parallel to the view direction",
+            ));
+        }
+        match self.projection {
+            Projection::Perspective if !(self.vfov > 0.0 && self.vfov < 180.0) => {
+                return Err(FieldError::new(
+                    "vfov",
+                    "field of view must be between 0 and 180 degrees",
+                ));
+            }
+            Projection::Fisheye if !(self.vfov > 0.0 && self.vfov <= 360.0) => {
+                return Err(FieldError::new(
+                    "vfov",
+                    "fisheye field of view must be between 0 and 360 degrees",
+                ));
+            }
+            Projection::Orthographic
+                if !(self.ortho_height.is_finite() && self.ortho_height > 0.0) =>
+            {
+                return Err(FieldError::new(
+                    "ortho_height",
+                    "orthographic height must be positive",
+                ));
+            }
+            _ => {}
+        }
+        if self.aperture.is_some() && self.fstop.is_some() {
+            return Err(FieldError::new(
+                "fstop",
+                "set either aperture or fstop, not both",
+            ));
+        }
+        if let Some(aperture) = self.aperture {
+            if !(aperture.is_finite() && aperture >= 0.0) {
+                return Err(FieldError::new("aperture", "aperture must not be negative"));
+            }
+        }
+        if let Some(fstop) = self.fstop {
+            if !(fstop.is_finite() && fstop > 0.0) {
+                return Err(FieldError::new("fstop", "f-stop must be positive"));
+            }
+        }
+        if let Some(focus_distance) = self.focus_distance {
+            if !(focus_distance.is_finite() && focus_distance > 0.0) {
+                return Err(FieldError::new(
+                    "focus_distance",
+                    "focus distance must be positive",
+                ));
+            }
+        }
+        if !(0.0..=1.0).contains(&self.shutter_open) {
+            return Err(FieldError::new(
+                "shutter_open",
+                "shutter times must be between 0 and 1",
+            ));
+        }
+        if !(self.shutter_open..=1.0).contains(&self.shutter_close) {
+            return Err(FieldError::new(
+                "shutter_close",
+                "shutter must close after it opens and by time 1",
+            ));
+        }
+        if self.blades == 1 || self.blades == 2 {
+            return Err(FieldError::new(
+                "blades",
+                "an aperture needs at least 3 blades, or 0 for a round one",
+            ));
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub struct Camera {
     pub image_width: u32,
     pub image_height: u32,
+    projection: Projection,
     origin: Point3,
     lower_left_corner: Vec3,
     horizontal: Vec3,
     vertical: Vec3,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+    aspect_ratio: f64,
+    vfov: f64,
+    ortho_height: f64,
+    lens_radius: f64,
+    blades: u32,
+    shutter_open: f64,
+    shutter_close: f64,
 }
 
 impl Camera {
-    pub fn new(image_width: u32, image_height: u32) -> Self {
+    pub fn new(image_width: u32, image_height: u32, settings: &SerializationCamera) -> Self {
         let aspect_ratio: f64 = image_width as f64 / image_height as f64;
 
-        let viewport_height: f64 = 2.0;
+        let viewport_height: f64 = 2.0 * (settings.vfov.to_radians() / 2.0).tan();
         let viewport_width: f64 = viewport_height * aspect_ratio;
-        let focal_length: f64 = 1.0;
 
-        let origin = Vec3::new(0.0, 0.0, 0.0);
-        let horizontal = Vec3::new(viewport_width, 0.0, 0.0);
-        let vertical = Vec3::new(0.0, viewport_height, 0.0);
+        // right-handed basis with w pointing backwards, away from what the camera looks at
+        let origin: Point3 = settings.lookfrom.into();
+        let w = (origin - settings.lookat.into()).normalize();
+        let u = Vec3::from(settings.vup).cross(&w).normalize();
+        let v = w.cross(&u);
+
+        // the viewport sits on the focus plane so rays from anywhere on the lens meet there
+        let focus_distance = settings.focus_distance();
+        let horizontal = u * (viewport_width * focus_distance);
+        let vertical = v * (viewport_height * focus_distance);
 
-        let lower_left_corner =
-            origin - horizontal / 2.0 - vertical / 2.0 - Vec3::new(0.0, 0.0, focal_length);
+        let lower_left_corner = origin - horizontal / 2.0 - vertical / 2.0 - w * focus_distance;
 
-        let res = Self {
+        Self {
             image_width,
             image_height,
+            projection: settings.projection,
             origin,
             lower_left_corner,
             horizontal,
             vertical,
+            u,
+            v,
+            w,
+            aspect_ratio,
+            vfov: settings.vfov.to_radians(),
+            ortho_height: settings.ortho_height,
+            lens_radius: settings.lens_radius(),
+            blades: settings.blades,
+            shutter_open: settings.shutter_open,
+            shutter_close: settings.shutter_close,
+        }
+    }
+
+    fn sample_lens(&self) -> Vec3 {
+        let (point, _) = if self.blades >= 3 {
+            sampling::regular_polygon(self.blades)
+        } else {
+            sampling::concentric_disk()
+        };
+        point * self.lens_radius
+    }
+
+    // `x` and `y` are continuous pixel coordinates with y growing upwards, None for pixels
+    // outside the image circle of a fisheye
+    pub fn get_ray(&self, width: u32, height: u32, x: f64, y: f64) -> Option<Ray> {
+        let x_ratio = x / width as f64;
+        let y_ratio = y / height as f64;
+        let time = self.shutter_open + (self.shutter_close - self.shutter_open) * rand_f64();
+        let ray = match self.projection {
+            Projection::Perspective => self.perspective_ray(x_ratio, y_ratio),
+            Projection::Orthographic => {
+                let origin = self.origin
+                    + self.u * ((x_ratio - 0.5) * self.ortho_height * self.aspect_ratio)
+                    + self.v * ((y_ratio - 0.5) * self.ortho_height);
+                Ray::new(origin, self.w * (-1.0))
+            }
+            Projection::Fisheye => {
+                // measured in image heights from the center
+                let px = (x_ratio - 0.5) * self.aspect_ratio;
+                let py = y_ratio - 0.5;
+                let r = (px * px + py * py).sqrt();
+                if r > 0.5 {
+                    return None;
+                }
+                let theta = r / 0.5 * self.vfov / 2.0;
+                let phi = py.atan2(px);
+                Ray::new(
+                    self.origin,
+                    self.direction(
+                        theta.sin() * phi.cos(),
+                        theta.sin() * phi.sin(),
+                        theta.cos(),
+                    ),
+                )
+            }
+            Projection::Equirectangular => {
+                let longitude = (x_ratio - 0.5) * 2.0 * PI;
+                let latitude = (y_ratio - 0.5) * PI;
+                Ray::new(
+                    self.origin,
+                    self.direction(
+                        latitude.cos() * longitude.sin(),
+                        latitude.sin(),
+                        latitude.cos() * longitude.cos(),
+                    ),
+                )
+            }
         };
+        Some(ray.with_time(time))
+    }
 
-        res
+    // camera space to world space, `forward` is along the view direction
+    fn direction(&self, right: f64, up: f64, forward: f64) -> Vec3 {
+        self.u * right + self.v * up - self.w * forward
     }
 
-    pub fn get_ray(&self, width: u32, height: u32, x: f64, y: f64) -> Ray {
-        let x_ratio = x as f64 / (width - 1) as f64;
-        let y_ratio = y as f64 / (height - 1) as f64;
-        Ray::new(
-            self.origin,
-            (self.lower_left_corner + self.horizontal * x_ratio + self.vertical * y_ratio
-                - self.origin)
-                .normalize(),
-        )
+    fn perspective_ray(&self, x_ratio: f64, y_ratio: f64) -> Ray {
+        let target = self.lower_left_corner + self.horizontal * x_ratio + self.vertical * y_ratio;
+
+        let origin = if self.lens_radius > 0.0 {
+            let lens = self.sample_lens();
+            self.origin + self.u * lens.x + self.v * lens.y
+        } else {
+            self.origin
+        };
+        Ray::new(origin, (target - origin).normalize())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Camera, Projection, SerializationCamera};
+    use crate::{maths::Vec3, random};
+
+    #[test]
+    fn look_at_basis_and_field_of_view() {
+        let settings = SerializationCamera {
+            lookfrom: (1.0, 2.0, 3.0),
+            lookat: (4.0, 0.0, -1.0),
+            vup: (0.0, 1.0, 0.0),
+            vfov: 60.0,
+            ..SerializationCamera::default()
+        };
+        let camera = Camera::new(200, 100, &settings);
+        let forward = (Vec3::from(settings.lookat) - settings.lookfrom.into()).normalize();
+
+        // orthonormal, right-handed, level with `vup` and looking at `lookat`
+        let (u, v, w) = (camera.u, camera.v, camera.w);
+        for (a, b) in [(u, v), (v, w), (w, u)] {
+            assert!((a * b).abs() < 1e-12);
+        }
+        for axis in [u, v, w] {
+            assert!((axis.length() - 1.0).abs() < 1e-12);
+        }
+        assert!((u.cross(&v) - w).length() < 1e-12);
+        assert!(u.y.abs() < 1e-12 && v.y > 0.0);
+        assert!((w + forward).length() < 1e-12);
+
+        let center = camera.get_ray(200, 100, 100.0, 50.0).unwrap();
+        assert!((center.direction - forward).length() < 1e-9);
+        assert!((center.origin - settings.lookfrom.into()).length() < 1e-12);
+        // `vfov` spans the image vertically, the width follows from the aspect ratio
+        let top = camera.get_ray(200, 100, 100.0, 100.0).unwrap();
+        assert!(((top.direction * forward).acos().to_degrees() - 30.0).abs() < 1e-9);
+        assert!(top.direction * v > 0.0);
+        let right = camera.get_ray(200, 100, 200.0, 50.0).unwrap();
+        let half_width = (2.0 * 30f64.to_radians().tan()).atan();
+        assert!(((right.direction * forward).acos() - half_width).abs() < 1e-9);
+        assert!(right.direction * u > 0.0);
+    }
+
+    #[test]
+    fn thin_lens_rays_meet_on_the_focus_plane() {
+        random::reseed(37);
+        let settings = SerializationCamera {
+            lookfrom: (1.0, 2.0, 3.0),
+            lookat: (0.0, 0.0, -1.0),
+            aperture: Some(0.5),
+            focus_distance: Some(4.0),
+            blades: 6,
+            ..SerializationCamera::default()
+        };
+        let camera = Camera::new(9, 7, &settings);
+        let focus = camera.lower_left_corner
+            + camera.horizontal * (2.0 / 9.0)
+            + camera.vertical * (5.0 / 7.0);
+
+        let forward = (Vec3::from(settings.lookat) - settings.lookfrom.into()).normalize();
+        assert!(((focus - camera.origin) * forward - 4.0).abs() < 1e-9);
+
+        let mut spread: f64 = 0.0;
+        for _ in 0..100 {
+            let ray = camera.get_ray(9, 7, 2.0, 5.0).unwrap();
+            spread = spread.max((ray.origin - camera.origin).length());
+            // the ray passes through the same point on the focus plane as the pinhole ray
+            let t = (focus - ray.origin).length();
+            assert!((ray.at(t) - focus).length() < 1e-9);
+        }
+        assert!(spread > 0.1 && spread <= 0.5 + 1e-9);
+    }
+
+    #[test]
+    fn projections_cover_their_field_of_view() {
+        let forward = Vec3::new(0.0, 0.0, -1.0);
+        let camera = |projection| {
+            Camera::new(
+                200,
+                100,
+                &SerializationCamera {
+                    projection,
+                    vfov: 180.0,
+                    ..SerializationCamera::default()
+                },
+            )
+        };
+
+        // the image center always looks along the view direction
+        for projection in [
+            Projection::Perspective,
+            Projection::Orthographic,
+            Projection::Fisheye,
+            Projection::Equirectangular,
+        ] {
+            let ray = camera(projection).get_ray(200, 100, 100.0, 50.0).unwrap();
+            assert!(
+                (ray.direction - forward).length() < 1e-9,
+                "{:?}",
+                projection
+            );
+        }
+
+        let equirectangular = camera(Projection::Equirectangular);
+        let behind = equirectangular.get_ray(200, 100, 0.0, 50.0).unwrap();
+        assert!((behind.direction - forward * (-1.0)).length() < 1e-9);
+        let up = equirectangular.get_ray(200, 100, 100.0, 100.0).unwrap();
+        assert!((up.direction - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-9);
+
+        // a 180 degree fisheye sees sideways at the rim of its image circle
+        let fisheye = camera(Projection::Fisheye);
+        let right = fisheye.get_ray(200, 100, 150.0, 50.0).unwrap();
+        assert!((right.direction - Vec3::new(1.0, 0.0, 0.0)).length() < 1e-9);
+        assert!(fisheye.get_ray(200, 100, 0.0, 0.0).is_none());
+
+        let orthographic = camera(Projection::Orthographic);
+        let corner = orthographic.get_ray(200, 100, 0.0, 0.0).unwrap();
+        assert!((corner.direction - forward).length() < 1e-9);
+        assert!((corner.origin - Vec3::new(-2.0, -1.0, 0.0)).length() < 1e-9);
+    }
+
+    #[test]
+    fn rays_are_timed_within_the_shutter() {
+        random::reseed(41);
+        let settings = SerializationCamera {
+            shutter_open: 0.25,
+            shutter_close: 0.75,
+            ..SerializationCamera::default()
+        };
+        let camera = Camera::new(4, 4, &settings);
+        let (mut earliest, mut latest) = (f64::INFINITY, f64::NEG_INFINITY);
+        for _ in 0..1000 {
+            let time = camera.get_ray(4, 4, 2.0, 2.0).unwrap().time;
+            assert!((0.25..=0.75).contains(&time), "{}", time);
+            earliest = earliest.min(time);
+            latest = latest.max(time);
+        }
+        // the whole interval is used
+        assert!(earliest < 0.26 && latest > 0.74);
     }
 }
@@ -1,46 +1,24 @@
-// use std::path::PathBuf;
-
-// use clap::{Parser, Subcommand};
-
-// #[derive(Parser)]
-// #[command(author, version, about, long_about = None)]
-// pub struct Cli {
-//     /// Sets a config file
-//     #[arg(short, long, value_name = "FILE")]
-//     config: Option<PathBuf>,
-
-//     #[command(subcommand)]
-//     command: Option<Commands>,
-// }
-
-// #[derive(Subcommand)]
-// enum Commands {
-//     /// Run tracer
-//     Run {
-//         #[arg(short, long, value_name = "SHADER")]
-//         shader: Option<String>,
-//     },
-// }
-
-// pub fn execute_args() {
-//     let cli = Cli::parse();
-
-//     if let Some(path) = cli.config.as_deref() {
-//     }
-
-// }
-
-use std::{fs, path::Path, rc::Rc};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::{fs, sync::Arc};
 
+use clap::{Args, Parser, Subcommand};
 use serde::Deserialize;
+use toml::Spanned;
 
+use crate::error::{FieldError, RaytError, Validate};
+use crate::hit::{Hittable, Surface};
+use crate::material::{MaterialLibrary, SerializationMaterial};
+use crate::objects::instance::SerializationInstance;
+use crate::objects::mesh::SerializationMesh;
 use crate::objects::plane::SerializationPlane;
 use crate::{
-    camera::*, const_vars::ConstContext, hit::HittableList, light::*, objects::*, renderer::*,
-    shaders::ShaderType,
+    bvh::BvhNode, camera::*, const_vars::ConstContext, hit::HittableList, light::*, objects::*,
+    output, preview, renderer::*, shaders::ShaderType, tonemap::DisplayTransform,
 };
 
 use crate::objects::sphere::SerializationSphere;
+use crate::texture::{SerializationTexture, TextureLibrary};
 #[derive(Deserialize)]
 pub struct Config {
     width: u32,
@@ -48,73 +26,741 @@ pub struct Config {
     samples: u32,
     max_depth: u32,
     render_type: String,
-    #[serde(rename = "Sphere")]
-    spheres: Vec<SerializationSphere>,
-    #[serde(rename = "Plane")]
-    planes: Vec<SerializationPlane>,
+    #[serde(rename = "Texture", default)]
+    textures: Vec<Spanned<SerializationTexture>>,
+    #[serde(rename = "Material", default)]
+    materials: Vec<Spanned<SerializationMaterial>>,
+    #[serde(rename = "Sphere", default)]
+    spheres: Vec<Spanned<SerializationSphere>>,
+    #[serde(rename = "Plane", default)]
+    planes: Vec<Spanned<SerializationPlane>>,
+    #[serde(rename = "Mesh", default)]
+    meshes: Vec<Spanned<SerializationMesh>>,
+    #[serde(rename = "Instance", default)]
+    instances: Vec<Spanned<SerializationInstance>>,
+    #[serde(rename = "Camera")]
+    camera: Option<Spanned<SerializationCamera>>,
+    #[serde(rename = "Environment")]
+    environment: Option<Spanned<SerializationEnvironment>>,
+    #[serde(rename = "SunLight", default)]
+    sun_lights: Vec<Spanned<SerializationSunLight>>,
+    #[serde(rename = "PointLight", default)]
+    point_lights: Vec<Spanned<SerializationPointLight>>,
+    #[serde(rename = "SpotLight", default)]
+    spot_lights: Vec<Spanned<SerializationSpotLight>>,
+    #[serde(rename = "AreaLight", default)]
+    area_lights: Vec<Spanned<SerializationAreaLight>>,
+    #[serde(rename = "Display")]
+    display: Option<Spanned<DisplayTransform>>,
+}
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+pub struct Cli {
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Render a scene to an image
+    Render {
+        /// Scene file
+        #[arg(value_name = "SCENE")]
+        scene: PathBuf,
+
+        /// Output image, .hdr, .pfm and .exr files keep the linear radiance
+        #[arg(short, long, value_name = "FILE", default_value = "test.png")]
+        output: PathBuf,
+
+        /// Show the image in a window while it converges, closing it saves the partial image
+        #[arg(long)]
+        preview: bool,
+
+        #[command(flatten)]
+        overrides: Overrides,
+    },
+    /// Parse a scene file without rendering it
+    Validate {
+        /// Scene file
+        #[arg(value_name = "SCENE")]
+        scene: PathBuf,
+    },
+}
+
+/// Settings that take precedence over the scene file
+#[derive(Args)]
+struct Overrides {
+    /// Image width in pixels
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    width: Option<u32>,
+
+    /// Image height in pixels
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    height: Option<u32>,
+
+    /// Samples per pixel
+    #[arg(short, long, value_parser = clap::value_parser!(u32).range(1..))]
+    samples: Option<u32>,
+
+    /// Maximum ray depth
+    #[arg(long)]
+    max_depth: Option<u32>,
+
+    /// Shader used to render the scene
+    #[arg(long, value_name = "SHADER")]
+    shader: Option<String>,
+
+    /// Seed for the random number generator, random if not set
+    #[arg(long)]
+    seed: Option<u64>,
+}
+
+impl Config {
+    fn apply(&mut self, overrides: &Overrides) {
+        if let Some(width) = overrides.width {
+            self.width = width;
+        }
+        if let Some(height) = overrides.height {
+            self.height = height;
+        }
+        if let Some(samples) = overrides.samples {
+            self.samples = samples;
+        }
+        if let Some(max_depth) = overrides.max_depth {
+            self.max_depth = max_depth;
+        }
+        if let Some(shader) = &overrides.shader {
+            self.render_type = shader.clone();
+        }
+    }
+}
+
+pub fn execute_args() -> Result<(), RaytError> {
+    let cli = Cli::parse();
+
+    match cli.command {
+        Commands::Render {
+            scene,
+            output,
+            preview,
+            overrides,
+        } => {
+            let mut config = init(&scene)?;
+            config.apply(&overrides);
+
+            let ctx = ConstContext {
+                samples_per_pixel: config.samples,
+                max_depth: config.max_depth,
+                output: true,
+                preview,
+                seed: overrides.seed.unwrap_or_else(rand::random),
+                config,
+            };
+            draw(ctx, &output)
+        }
+        Commands::Validate { scene } => {
+            init(&scene)?;
+            println!("{}: ok", scene.display());
+            Ok(())
+        }
+    }
+}
+
+fn line_of(source: &str, offset: usize) -> usize {
+    source[..offset].matches('\n').count() + 1
+}
+
+// top-level keys come before the first table header
+fn key_line(source: &str, key: &str) -> Option<usize> {
+    source
+        .lines()
+        .take_while(|line| !line.trim_start().starts_with('['))
+        .position(|line| {
+            line.split('=')
+                .next()
+                .is_some_and(|name| name.trim() == key)
+        })
+        .map(|index| index + 1)
+}
+
+fn validate_entry<T: Validate>(
+    source: &str,
+    key: &str,
+    entry: &Spanned<T>,
+) -> Result<(), RaytError> {
+    entry
+        .get_ref()
+        .validate()
+        .map_err(|FieldError { field, message }| RaytError::Validation {
+            key: format!("{}.{}", key, field),
+            line: Some(line_of(source, entry.span().start)),
+            message,
+        })
+}
+
+fn validate_table<T: Validate>(
+    source: &str,
+    table: &str,
+    entries: &[Spanned<T>],
+) -> Result<(), RaytError> {
+    for (index, entry) in entries.iter().enumerate() {
+        validate_entry(source, &format!("{}[{}]", table, index), entry)?;
+    }
+    Ok(())
+}
+
+// `field` of every entry has to name one of `names`, e.g. the material of an object
+fn validate_references<T>(
+    source: &str,
+    table: &str,
+    field: &str,
+    entries: &[Spanned<T>],
+    names: &HashSet<&str>,
+    reference: impl Fn(&T) -> &str,
+) -> Result<(), RaytError> {
+    for (index, entry) in entries.iter().enumerate() {
+        let name = reference(entry.get_ref());
+        if !names.contains(name) {
+            return Err(RaytError::Validation {
+                key: format!("{}[{}].{}", table, index, field),
+                line: Some(line_of(source, entry.span().start)),
+                message: format!("unknown {} `{}`", field, name),
+            });
+        }
+    }
+    Ok(())
+}
+
+// names given by `name` are added to `names` and must not be there already, entries without
+// one are skipped
+fn unique_names<'a, T>(
+    source: &str,
+    table: &str,
+    kind: &str,
+    entries: &'a [Spanned<T>],
+    name: impl Fn(&'a T) -> Option<&'a str>,
+    names: &mut HashSet<&'a str>,
+) -> Result<(), RaytError> {
+    for (index, entry) in entries.iter().enumerate() {
+        if let Some(name) = name(entry.get_ref()) {
+            if !names.insert(name) {
+                return Err(RaytError::Validation {
+                    key: format!("{}[{}].name", table, index),
+                    line: Some(line_of(source, entry.span().start)),
+                    message: format!("{} `{}` is defined more than once", kind, name),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Config {
+    fn validate(&self, source: &str) -> Result<(), RaytError> {
+        for (key, value) in [
+            ("width", self.width),
+            ("height", self.height),
+            ("samples", self.samples),
+        ] {
+            if value == 0 {
+                return Err(RaytError::Validation {
+                    key: key.to_string(),
+                    line: key_line(source, key),
+                    message: "must be at least 1".to_string(),
+                });
+            }
+        }
+
+        if let Err(RaytError::UnknownShader { name, .. }) = self.render_type.parse::<ShaderType>() {
+            return Err(RaytError::UnknownShader {
+                name,
+                line: key_line(source, "render_type"),
+            });
+        }
+
+        if let Some(camera) = &self.camera {
+            validate_entry(source, "Camera", camera)?;
+        }
+
+        if let Some(display) = &self.display {
+            validate_entry(source, "Display", display)?;
+        }
+
+        if let Some(environment) = &self.environment {
+            validate_entry(source, "Environment", environment)?;
+        }
+        validate_table(source, "SunLight", &self.sun_lights)?;
+        validate_table(source, "PointLight", &self.point_lights)?;
+        validate_table(source, "SpotLight", &self.spot_lights)?;
+        validate_table(source, "AreaLight", &self.area_lights)?;
+
+        validate_table(source, "Texture", &self.textures)?;
+        let mut textures = HashSet::new();
+        unique_names(
+            source,
+            "Texture",
+            "texture",
+            &self.textures,
+            |i| Some(i.name()),
+            &mut textures,
+        )?;
+
+        validate_table(source, "Material", &self.materials)?;
+        let mut materials = HashSet::new();
+        unique_names(
+            source,
+            "Material",
+            "material",
+            &self.materials,
+            |i| Some(i.name()),
+            &mut materials,
+        )?;
+        for (index, material) in self.materials.iter().enumerate() {
+            for (field, name) in material.get_ref().textures() {
+                if !textures.contains(name) {
+                    return Err(RaytError::Validation {
+                        key: format!("Material[{}].{}", index, field),
+                        line: Some(line_of(source, material.span().start)),
+                        message: format!("unknown texture `{}`", name),
+                    });
+                }
+            }
+        }
+        // normal maps hold directions, not colors, and must be read as they are stored
+        let srgb: HashSet<&str> = self
+            .textures
+            .iter()
+            .map(|i| i.get_ref())
+            .filter(|i| i.is_srgb())
+            .map(|i| i.name())
+            .collect();
+        for (index, material) in self.materials.iter().enumerate() {
+            if let Some(name) = material.get_ref().normal_map() {
+                if srgb.contains(name) {
+                    return Err(RaytError::Validation {
+                        key: format!("Material[{}].normal_map", index),
+                        line: Some(line_of(source, material.span().start)),
+                        message: format!(
+                            "texture `{}` needs `linear = true` to be a normal map",
+                            name
+                        ),
+                    });
+                }
+            }
+        }
+
+        validate_table(source, "Sphere", &self.spheres)?;
+        validate_table(source, "Plane", &self.planes)?;
+        validate_table(source, "Mesh", &self.meshes)?;
+        validate_table(source, "Instance", &self.instances)?;
+
+        validate_references(
+            source,
+            "Sphere",
+            "material",
+            &self.spheres,
+            &materials,
+            |i| i.material(),
+        )?;
+        validate_references(source, "Plane", "material", &self.planes, &materials, |i| {
+            i.material()
+        })?;
+        validate_references(source, "Mesh", "material", &self.meshes, &materials, |i| {
+            i.material()
+        })?;
+
+        // spheres, planes and meshes share one namespace for instances to refer to
+        let mut objects = HashSet::new();
+        unique_names(
+            source,
+            "Sphere",
+            "object",
+            &self.spheres,
+            |i| i.name(),
+            &mut objects,
+        )?;
+        unique_names(
+            source,
+            "Plane",
+            "object",
+            &self.planes,
+            |i| i.name(),
+            &mut objects,
+        )?;
+        unique_names(
+            source,
+            "Mesh",
+            "object",
+            &self.meshes,
+            |i| i.name(),
+            &mut objects,
+        )?;
+        validate_references(
+            source,
+            "Instance",
+            "object",
+            &self.instances,
+            &objects,
+            |i| i.object(),
+        )
+    }
+}
+
+pub fn init(scene: &Path) -> Result<Config, RaytError> {
+    let source = fs::read_to_string(scene).map_err(|source| RaytError::Io {
+        path: scene.to_path_buf(),
+        source,
+    })?;
+    let mut config: Config = toml::from_str(&source).map_err(|source| RaytError::Parse {
+        path: scene.to_path_buf(),
+        source,
+    })?;
+    config.validate(&source)?;
+
+    let scene_dir = scene.parent().unwrap_or(Path::new(""));
+    for mesh in &mut config.meshes {
+        mesh.get_mut().resolve_path(scene_dir);
+    }
+    for texture in &mut config.textures {
+        texture.get_mut().resolve_path(scene_dir);
+    }
+    if let Some(environment) = &mut config.environment {
+        environment.get_mut().resolve_path(scene_dir);
+    }
+
+    Ok(config)
 }
 
-pub fn init() -> ConstContext {
-    let config = fs::read_to_string(Path::new("config.toml")).unwrap();
-    let config: Config = toml::from_str(&config).unwrap();
+// a named object placed only through instances, with the surfaces they sample if it glows
+struct Prototype<'a> {
+    object: Arc<dyn Hittable>,
+    material: &'a str,
+    surfaces: Vec<Arc<dyn Surface>>,
+    // planes and meshes stay evenly stretched under any scale, spheres do not
+    flat: bool,
+}
 
-    ConstContext {
-        samples_per_pixel: config.samples,
-        max_depth: config.max_depth,
-        output: true,
-        config,
+impl<'a> Prototype<'a> {
+    fn new(
+        object: Arc<dyn Hittable>,
+        material: &'a str,
+        surfaces: Vec<Arc<dyn Surface>>,
+        flat: bool,
+    ) -> Self {
+        Self {
+            object,
+            material,
+            surfaces,
+            flat,
+        }
     }
 }
 
-pub fn draw(ctx: ConstContext) {
+pub fn draw(ctx: ConstContext, output: &Path) -> Result<(), RaytError> {
     //create camera
-    let camera = Camera::new(ctx.config.width, ctx.config.height);
+    let default_camera = SerializationCamera::default();
+    let camera_settings = ctx
+        .config
+        .camera
+        .as_ref()
+        .map_or(&default_camera, |camera| camera.get_ref());
+    let camera = Camera::new(ctx.config.width, ctx.config.height, camera_settings);
 
     //create light group
     let mut light_group = LightGroup::new();
-    light_group.add(Light::HDRILight(HDRILight::new(
-        [1.0, 1.0, 1.0].into(),
-        1.0,
-    )));
-    /*light_group.add(Light::SunLight(SunLight::new(
-        [1.0, -0.6, -1.0].into(),
-        1.0,
-        [1.0, 1.0, 1.0].into(),
-    )));
-    light_group.add(Light::PointLight(PointLight::new(
-        [-8.0, 8.0, -6.0].into(),
-        0.5,
-        1.0,
-        [1.0, 1.0, 1.0].into(),
-    )));*/
+
+    // without an environment the background stays black
+    if let Some(environment) = &ctx.config.environment {
+        light_group.add(Light::HDRILight(HDRILight::try_from(
+            environment.get_ref(),
+        )?));
+    }
+
+    for i in &ctx.config.sun_lights {
+        light_group.add(Light::SunLight(SunLight::from(i.get_ref())));
+    }
+
+    for i in &ctx.config.point_lights {
+        light_group.add(Light::PointLight(PointLight::from(i.get_ref())));
+    }
+
+    for i in &ctx.config.spot_lights {
+        light_group.add(Light::SpotLight(SpotLight::from(i.get_ref())));
+    }
+
+    for i in &ctx.config.area_lights {
+        light_group.add(Light::AreaLight(AreaLight::from(i.get_ref())));
+    }
+
+    //create textures and materials
+    let textures = TextureLibrary::new(ctx.config.textures.iter().map(|i| i.get_ref()))?;
+    let materials =
+        MaterialLibrary::new(ctx.config.materials.iter().map(|i| i.get_ref()), &textures);
 
     //create world with objects
     let mut world = HittableList::new();
 
+    // surfaces with an emissive material, gathered into one light per material
+    let mut emitters: HashMap<&str, Vec<Arc<dyn Surface>>> = ctx
+        .config
+        .materials
+        .iter()
+        .filter(|i| i.get_ref().emission().is_some())
+        .map(|i| (i.get_ref().name(), vec![]))
+        .collect();
+
+    // named objects are only placed through instances, which share their geometry
+    let mut prototypes: HashMap<&str, Prototype> = HashMap::new();
+
     for i in &ctx.config.spheres {
-        world.add(Rc::new(Sphere::from(i)));
+        let i = i.get_ref();
+        let sphere = Arc::new(Sphere::from_serialization(i, materials.get(i.material())));
+        match i.name() {
+            Some(name) => {
+                let prototype = Prototype::new(sphere.clone(), i.material(), vec![sphere], false);
+                prototypes.insert(name, prototype);
+            }
+            None => {
+                if let Some(surfaces) = emitters.get_mut(i.material()) {
+                    surfaces.push(sphere.clone());
+                }
+                world.add(sphere);
+            }
+        }
     }
 
     for i in &ctx.config.planes {
-        world.add(Rc::new(Plane::from(i)));
+        let i = i.get_ref();
+        let plane = Arc::new(Plane::from_serialization(i, materials.get(i.material())));
+        match i.name() {
+            Some(name) => {
+                let prototype = Prototype::new(plane.clone(), i.material(), vec![plane], true);
+                prototypes.insert(name, prototype);
+            }
+            None => {
+                if let Some(surfaces) = emitters.get_mut(i.material()) {
+                    surfaces.push(plane.clone());
+                }
+                world.add(plane);
+            }
+        }
+    }
+
+    for i in &ctx.config.meshes {
+        let i = i.get_ref();
+        let mesh = Arc::new(Mesh::from_serialization(i, materials.get(i.material()))?);
+        let triangles = mesh
+            .triangles()
+            .iter()
+            .map(|triangle| triangle.clone() as Arc<dyn Surface>);
+        match i.name() {
+            Some(name) => {
+                let prototype =
+                    Prototype::new(mesh.clone(), i.material(), triangles.collect(), true);
+                prototypes.insert(name, prototype);
+            }
+            None => {
+                if let Some(surfaces) = emitters.get_mut(i.material()) {
+                    surfaces.extend(triangles);
+                }
+                world.add(mesh);
+            }
+        }
+    }
+
+    for i in &ctx.config.instances {
+        let i = i.get_ref();
+        let prototype = &prototypes[i.object()];
+        let instance = Instance::from_serialization(i, prototype.object.clone());
+        match emitters.get_mut(prototype.material) {
+            // the others still glow, only bsdf samples find them
+            Some(surfaces) if instance.samples_uniformly(prototype.flat) => {
+                let instance = Arc::new(instance.with_surfaces(prototype.surfaces.clone()));
+                surfaces.push(instance.clone());
+                world.add(instance);
+            }
+            _ => world.add(Arc::new(instance)),
+        }
     }
 
+    for i in &ctx.config.materials {
+        let i = i.get_ref();
+        match (i.emission(), emitters.remove(i.name())) {
+            (Some(radiance), Some(surfaces)) if !surfaces.is_empty() => {
+                light_group.add(Light::EmissiveLight(EmissiveLight::new(radiance, surfaces)));
+            }
+            _ => {}
+        }
+    }
+
+    let world = BvhNode::from(&world);
+
     //render
-    let renderer = if ctx.config.render_type == "PathTracing" {
-        Renderer::new(
-            world,
-            light_group,
-            camera,
-            ctx,
-            ShaderType::PathTracing,
-            0.8,
-            2.2,
-        )
+    let shader_type = ctx.config.render_type.parse::<ShaderType>()?;
+    let display = ctx
+        .config
+        .display
+        .as_ref()
+        .map_or_else(DisplayTransform::default, |display| *display.get_ref());
+    let renderer = Renderer::new(
+        Box::new(world),
+        light_group,
+        camera,
+        ctx,
+        shader_type,
+        display,
+    );
+    let img = if renderer.ctx.preview {
+        preview::render_preview(&renderer)?
     } else {
-        todo!()
+        renderer.render()?
     };
-    let img = renderer.render();
 
     //output image
-    img.save("test.png").unwrap();
+    output::save(&img, output, &renderer.display)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use clap::Parser;
+
+    use super::{init, Cli, Commands, Config};
+    use crate::error::RaytError;
+    use crate::light::{AreaLight, HDRILight, PointLight, SpotLight, SunLight};
+
+    const LIGHTS: &str = r#"
+width = 16
+height = 16
+samples = 1
+max_depth = 2
+render_type = "PathTracing"
+
+[Environment]
+color = [0.2, 0.3, 0.4]
+intensity = 0.5
+
+[[SunLight]]
+direction = [0.0, -1.0, -1.0]
+angle = 0.5
+color = [1.0, 0.9, 0.8]
+intensity = 3.0
+
+[[PointLight]]
+origin = [0.0, 2.0, 0.0]
+size = 0.1
+color = [1.0, 1.0, 1.0]
+
+[[SpotLight]]
+origin = [0.0, 3.0, 0.0]
+direction = [0.0, -1.0, 0.0]
+angle = 30.0
+blend = 0.2
+color = [1.0, 1.0, 1.0]
+intensity = 20.0
+
+[[AreaLight]]
+origin = [-0.5, 2.0, -0.5]
+edge_x = [1.0, 0.0, 0.0]
+edge_y = [0.0, 0.0, 1.0]
+color = [1.0, 1.0, 1.0]
+"#;
+
+    #[test]
+    fn scene_declares_every_light() {
+        let path = std::env::temp_dir().join(format!("rayt-lights-{}.toml", std::process::id()));
+        fs::write(&path, LIGHTS).unwrap();
+        let config = init(&path);
+        fs::remove_file(&path).unwrap();
+        let config = config.unwrap();
+
+        let environment = HDRILight::try_from(config.environment.as_ref().unwrap().get_ref());
+        assert_eq!(environment.unwrap().intensity, 0.5);
+        let sun = SunLight::from(config.sun_lights[0].get_ref());
+        assert!((sun.angle - 0.5f64.to_radians()).abs() < 1e-12);
+        let point = PointLight::from(config.point_lights[0].get_ref());
+        assert_eq!(point.size, 0.1);
+        let spot = SpotLight::from(config.spot_lights[0].get_ref());
+        assert!((spot.angle - 30f64.to_radians()).abs() < 1e-12);
+        let area = AreaLight::from(config.area_lights[0].get_ref());
+        assert_eq!(area.intensity, 1.0);
+    }
+
+    #[test]
+    fn invalid_light_reports_key_and_line() {
+        let source = LIGHTS.replace("blend = 0.2", "blend = 1.5");
+        let config: Config = toml::from_str(&source).unwrap();
+        match config.validate(&source) {
+            Err(RaytError::Validation { key, line, .. }) => {
+                assert_eq!(key, "SpotLight[0].blend");
+                assert_eq!(line, Some(23));
+            }
+            _ => panic!("expected a validation error"),
+        }
+    }
+
+    #[test]
+    fn flags_override_the_scene_file() {
+        let mut config: Config = toml::from_str(
+            "width = 16\nheight = 9\nsamples = 4\nmax_depth = 5\nrender_type = \"PathTracing\"",
+        )
+        .unwrap();
+        let args = [
+            "rayt",
+            "render",
+            "scene.toml",
+            "--width",
+            "32",
+            "-s",
+            "64",
+            "--shader",
+            "LeadTest",
+        ];
+        let overrides = match Cli::parse_from(args).command {
+            Commands::Render { overrides, .. } => overrides,
+            _ => panic!("expected the render command"),
+        };
+        config.apply(&overrides);
+
+        assert_eq!((config.width, config.samples), (32, 64));
+        assert_eq!(config.render_type, "LeadTest");
+        // settings without a flag keep their scene values
+        assert_eq!((config.height, config.max_depth), (9, 5));
+        assert!(Cli::try_parse_from(["rayt", "render", "scene.toml", "--width", "0"]).is_err());
+    }
+
+    #[test]
+    fn validation_reports_key_and_line() {
+        let source = r#"
+width = 16
+height = 16
+samples = 1
+max_depth = 2
+render_type = "PathTracing"
+
+[[Material]]
+name = "grey"
+type = "Standard"
+color = [0.5, 0.5, 0.5]
+roughness = 0.5
+reflectivity = 0.5
+
+[[Material]]
+name = "rough"
+type = "Standard"
+color = [0.5, 0.5, 0.5]
+roughness = 1.5
+reflectivity = 0.5
+"#;
+        let config: Config = toml::from_str(source).unwrap();
+        match config.validate(source) {
+            Err(RaytError::Validation { key, line, .. }) => {
+                assert_eq!(key, "Material[1].roughness");
+                assert_eq!(line, Some(15));
+            }
+            _ => panic!("expected a validation error"),
+        }
+    }
 }
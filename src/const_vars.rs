@@ -4,5 +4,7 @@ pub struct ConstContext {
     pub samples_per_pixel: u32,
     pub max_depth: u32,
     pub output: bool,
+    pub preview: bool,
+    pub seed: u64,
     pub config: Config,
 }
@@ -0,0 +1,167 @@
+use core::f64::consts::PI;
+use std::path::Path;
+
+use crate::{
+    error::RaytError,
+    maths::{Color, Vec3},
+    random::rand_f64,
+    sampling::Distribution1D,
+};
+
+// equirectangular panorama, the image center looks down -z and the top row straight up
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+    // rotation around the vertical axis in radians
+    rotation: f64,
+    rows: Vec<Distribution1D>,
+    marginal: Distribution1D,
+}
+
+impl EnvironmentMap {
+    pub fn load(path: &Path, rotation: f64) -> Result<Self, RaytError> {
+        let img = image::open(path)
+            .map_err(|source| RaytError::Image {
+                path: path.to_path_buf(),
+                source,
+            })?
+            .into_rgb32f();
+        let pixels = img
+            .pixels()
+            .map(|p| Color::new(p[0] as f64, p[1] as f64, p[2] as f64))
+            .collect();
+        Ok(Self::new(
+            img.width() as usize,
+            img.height() as usize,
+            pixels,
+            rotation,
+        ))
+    }
+
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>, rotation: f64) -> Self {
+        // rows near the poles cover less solid angle, weight them by sin(theta)
+        let rows: Vec<Distribution1D> = (0..height)
+            .map(|y| {
+                let sin_theta = ((y as f64 + 0.5) / height as f64 * PI).sin();
+                Distribution1D::new(
+                    (0..width)
+                        .map(|x| pixels[y * width + x].luminance().max(0.0) * sin_theta)
+                        .collect(),
+                )
+            })
+            .collect();
+        let marginal = Distribution1D::new(rows.iter().map(|row| row.integral()).collect());
+        Self {
+            width,
+            height,
+            pixels,
+            rotation,
+            rows,
+            marginal,
+        }
+    }
+
+    fn direction_to_uv(&self, dir: Vec3) -> (f64, f64) {
+        let phi = dir.x.atan2(-dir.z) - self.rotation;
+        let u = (phi / (2.0 * PI) + 0.5).rem_euclid(1.0);
+        let v = dir.y.clamp(-1.0, 1.0).acos() / PI;
+        (u, v)
+    }
+
+    fn uv_to_direction(&self, u: f64, v: f64) -> Vec3 {
+        let phi = (u - 0.5) * 2.0 * PI + self.rotation;
+        let theta = v * PI;
+        Vec3::new(
+            theta.sin() * phi.sin(),
+            theta.cos(),
+            -theta.sin() * phi.cos(),
+        )
+    }
+
+    fn texel(&self, u: f64, v: f64) -> (usize, usize) {
+        let x = ((u * self.width as f64) as usize).min(self.width - 1);
+        let y = ((v * self.height as f64) as usize).min(self.height - 1);
+        (x, y)
+    }
+
+    pub fn lookup(&self, dir: Vec3) -> Color {
+        let (u, v) = self.direction_to_uv(dir);
+        let (x, y) = self.texel(u, v);
+        self.pixels[y * self.width + x]
+    }
+
+    // direction drawn proportionally to luminance, with its solid angle density
+    pub fn sample(&self) -> Option<(Vec3, f64)> {
+        let (v, _, y) = self.marginal.sample(rand_f64());
+        let (u, _, _) = self.rows[y].sample(rand_f64());
+        let dir = self.uv_to_direction(u, v);
+        let pdf = self.pdf(dir);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some((dir, pdf))
+    }
+
+    pub fn pdf(&self, dir: Vec3) -> f64 {
+        let (u, v) = self.direction_to_uv(dir);
+        let sin_theta = (v * PI).sin();
+        if sin_theta <= 0.0 || self.marginal.integral() <= 0.0 {
+            return 0.0;
+        }
+        let (x, y) = self.texel(u, v);
+        let pdf_uv = self.marginal.pdf(y) * self.rows[y].pdf(x);
+        // the (u, v) square maps to 2 pi x pi radians with an area element of sin(theta)
+        pdf_uv / (2.0 * PI * PI * sin_theta)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EnvironmentMap;
+    use crate::{maths::Color, random};
+
+    #[test]
+    fn sampling_matches_pdf_and_radiance() {
+        random::reseed(11);
+        // dim sky with one bright texel
+        let (width, height) = (32, 16);
+        let mut pixels = vec![Color::new(0.1, 0.1, 0.1); width * height];
+        pixels[5 * width + 20] = Color::new(500.0, 400.0, 300.0);
+        let map = EnvironmentMap::new(width, height, pixels, 0.7);
+
+        // the estimate of the total power has to agree with the texel sum
+        let n = 100000;
+        let mut estimate = 0.0;
+        let mut bright = 0;
+        for _ in 0..n {
+            let (dir, pdf) = map.sample().unwrap();
+            assert!((map.pdf(dir) - pdf).abs() < 1e-9 * pdf.max(1.0));
+            let radiance = map.lookup(dir);
+            estimate += radiance.y / pdf;
+            if radiance.y > 1.0 {
+                bright += 1;
+            }
+        }
+        estimate /= n as f64;
+
+        let mut expected = 0.0;
+        for y in 0..height {
+            let theta0 = y as f64 / height as f64 * std::f64::consts::PI;
+            let theta1 = (y + 1) as f64 / height as f64 * std::f64::consts::PI;
+            let solid_angle =
+                2.0 * std::f64::consts::PI / width as f64 * (theta0.cos() - theta1.cos());
+            for x in 0..width {
+                expected += map.pixels[y * width + x].y * solid_angle;
+            }
+        }
+        assert!(
+            (estimate - expected).abs() < 0.02 * expected,
+            "{} {}",
+            estimate,
+            expected
+        );
+        // most samples should land on the bright texel
+        assert!(bright > n / 2, "{}", bright);
+    }
+}
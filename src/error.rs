@@ -0,0 +1,118 @@
+use std::{error::Error, fmt, io, path::PathBuf};
+
+#[derive(Debug)]
+pub enum RaytError {
+    Io {
+        path: PathBuf,
+        source: io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    Validation {
+        key: String,
+        line: Option<usize>,
+        message: String,
+    },
+    UnknownShader {
+        name: String,
+        line: Option<usize>,
+    },
+    Image {
+        path: PathBuf,
+        source: image::ImageError,
+    },
+    Preview(minifb::Error),
+}
+
+// a problem with one field of a scene entry, turned into a `RaytError::Validation`
+// once the caller knows which table and line the entry came from
+#[derive(Debug)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            field,
+            message: message.into(),
+        }
+    }
+}
+
+pub trait Validate {
+    fn validate(&self) -> Result<(), FieldError>;
+}
+
+fn fmt_line(f: &mut fmt::Formatter<'_>, line: Option<usize>) -> fmt::Result {
+    match line {
+        Some(line) => write!(f, " (line {})", line),
+        None => Ok(()),
+    }
+}
+
+impl fmt::Display for RaytError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RaytError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            RaytError::Parse { path, source } => write!(f, "{}: {}", path.display(), source),
+            RaytError::Validation { key, line, message } => {
+                write!(f, "invalid value for `{}`", key)?;
+                fmt_line(f, *line)?;
+                write!(f, ": {}", message)
+            }
+            RaytError::UnknownShader { name, line } => {
+                write!(f, "unknown shader `{}` for `render_type`", name)?;
+                fmt_line(f, *line)?;
+                write!(f, ", expected one of: PathTracing, LeadTest")
+            }
+            RaytError::Image { path, source } => write!(f, "{}: {}", path.display(), source),
+            RaytError::Preview(source) => write!(f, "preview window: {}", source),
+        }
+    }
+}
+
+impl Error for RaytError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RaytError::Io { source, .. } => Some(source),
+            RaytError::Parse { source, .. } => Some(source),
+            RaytError::Image { source, .. } => Some(source),
+            RaytError::Preview(source) => Some(source),
+            RaytError::Validation { .. } | RaytError::UnknownShader { .. } => None,
+        }
+    }
+}
+
+pub fn check_color(field: &'static str, color: (f64, f64, f64)) -> Result<(), FieldError> {
+    if [color.0, color.1, color.2]
+        .iter()
+        .all(|c| c.is_finite() && *c >= 0.0)
+    {
+        Ok(())
+    } else {
+        Err(FieldError::new(
+            field,
+            "color components must not be negative",
+        ))
+    }
+}
+
+pub fn check_non_negative(field: &'static str, value: f64) -> Result<(), FieldError> {
+    if value.is_finite() && value >= 0.0 {
+        Ok(())
+    } else {
+        Err(FieldError::new(field, "must not be negative"))
+    }
+}
+
+pub fn check_unit(field: &'static str, value: f64) -> Result<(), FieldError> {
+    if (0.0..=1.0).contains(&value) {
+        Ok(())
+    } else {
+        Err(FieldError::new(field, "must be between 0 and 1"))
+    }
+}
@@ -1,29 +1,83 @@
-use std::rc::Rc;
+use std::sync::Arc;
 
-use crate::{maths::{Vec3, Color}, ray::Ray};
+use crate::{
+    aabb::Aabb,
+    material::Material,
+    maths::{Point3, Vec3},
+    ray::Ray,
+};
 
+// which side of the surface the ray came from, `Inward` means it is entering the object
 pub enum Front {
     Inward,
     Outward,
 }
 
+impl Front {
+    // hit records keep the normal facing against the ray, so materials always work on the
+    // side the ray arrived from and read `Front` to tell entering from exiting
+    pub fn from_outward_normal(ray: &Ray, outward_normal: Vec3) -> (Front, Vec3) {
+        if ray.direction * outward_normal < 0.0 {
+            (Front::Inward, outward_normal)
+        } else {
+            (Front::Outward, outward_normal * (-1.0))
+        }
+    }
+}
+
+// the hit on the object itself, before any instance transform or motion, procedural textures
+// are evaluated there so they stay attached to the object
+#[derive(Clone, Copy)]
+pub struct ObjectSpace {
+    pub point: Point3,
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
+}
+
 pub struct HitRecord<'a> {
     pub obj: &'a dyn Hittable,
+    pub material: &'a dyn Material,
     pub point: Vec3,
+    // shading normal, interpolated or perturbed, which materials work with
     pub normal: Vec3,
+    // normal of the actual surface, on the same side as `normal`
+    pub geometric_normal: Vec3,
+    // surface coordinates for textures, both usually within [0, 1]
+    pub uv: (f64, f64),
+    // how the point moves with u and v, tangent to the surface but not normalized
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
+    pub object: ObjectSpace,
     pub front_face: Front,
     pub t: f64,
 }
 
-pub trait Hittable {
-    fn get_hit_record(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
-    fn get_color(&self) -> Color;
-    fn get_roughness(&self) -> f64;
-    fn get_reflectivity(&self) -> f64;
+impl HitRecord<'_> {
+    pub fn is_entering(&self) -> bool {
+        matches!(self.front_face, Front::Inward)
+    }
+
+    // a direction the shading normal puts on the other side of the surface than the
+    // geometric one would leak light through it
+    pub fn is_consistent(&self, wi: Vec3) -> bool {
+        (wi * self.normal) * (wi * self.geometric_normal) > 0.0
+    }
+}
+
+pub trait Hittable: Send + Sync {
+    fn get_hit_record(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>>;
+    fn bounding_box(&self) -> Aabb;
+}
+
+// a surface that can be sampled uniformly by area, emissive objects become lights through it
+pub trait Surface: Send + Sync {
+    fn area(&self) -> f64;
+    // a point on the surface at `time` with the outward normal there
+    fn sample_point(&self, time: f64) -> (Point3, Vec3);
 }
 
 pub struct HittableList {
-    objects: Vec<Rc<dyn Hittable>>,
+    objects: Vec<Arc<dyn Hittable>>,
 }
 
 impl HittableList {
@@ -31,17 +85,17 @@ impl HittableList {
         Self { objects: vec![] }
     }
 
-    pub fn clear(&mut self) {
-        self.objects.clear();
+    pub fn add(&mut self, obj: Arc<dyn Hittable>) {
+        self.objects.push(obj)
     }
 
-    pub fn add(&mut self, obj: Rc<dyn Hittable>) {
-        self.objects.push(obj)
+    pub fn objects(&self) -> &[Arc<dyn Hittable>] {
+        &self.objects
     }
 }
 
 impl Hittable for HittableList {
-    fn get_hit_record(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn get_hit_record(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let mut closest = t_max;
         let mut result = None;
         for object in &self.objects {
@@ -55,13 +109,9 @@ impl Hittable for HittableList {
         }
         result
     }
-    fn get_color(&self) -> Color {
-        Color::new(0.0, 0.0, 0.0)
-    }
-    fn get_roughness(&self) -> f64 {
-        0.0
-    }
-    fn get_reflectivity(&self) -> f64 {
-        0.0
+    fn bounding_box(&self) -> Aabb {
+        self.objects.iter().fold(Aabb::empty(), |bbox, object| {
+            bbox.union(&object.bounding_box())
+        })
     }
 }
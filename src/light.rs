@@ -1,11 +1,52 @@
+use core::f64::consts::PI;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::Deserialize;
+
+use crate::environment::EnvironmentMap;
+use crate::hit::{Hittable, Surface};
+
+use crate::error::{check_color, check_non_negative, check_unit, FieldError, RaytError, Validate};
 use crate::maths::{Point3, Vec3, Color, HDR};
+use crate::random::rand_f64;
+use crate::ray::{Ray, SHADOW_EPSILON};
+use crate::sampling::{self, Distribution1D};
 
+#[allow(clippy::enum_variant_names)]
 pub enum Light {
     HDRILight(HDRILight),
     SunLight(SunLight),
     PointLight(PointLight),
     SpotLight(SpotLight),
     AreaLight(AreaLight),
+    EmissiveLight(EmissiveLight),
+}
+
+// a direction towards a light as seen from a shading point
+pub struct LightSample {
+    pub wi: Vec3,
+    pub distance: f64,
+    // incident radiance, for point-like lights the value already divided by the squared distance
+    pub radiance: HDR,
+    // solid angle density of `wi`, 1 for point-like lights that can only be reached by sampling them
+    pub pdf: f64,
+    pub delta: bool,
+}
+
+impl Light {
+    // `time` places lights made from moving objects
+    pub fn sample(&self, point: Point3, time: f64) -> Option<LightSample> {
+        match self {
+            Light::PointLight(light) => light.sample(point),
+            Light::SpotLight(light) => light.sample(point),
+            Light::AreaLight(light) => light.sample(point),
+            Light::EmissiveLight(light) => light.sample(point, time),
+            Light::SunLight(light) => Some(light.sample()),
+            Light::HDRILight(light) => light.sample(),
+        }
+    }
 }
 
 pub struct LightGroup {
@@ -20,39 +61,95 @@ impl LightGroup {
     pub fn add(&mut self, light: Light) {
         self.lights.push(light);
     }
-
-    pub fn clear(&mut self) {
-        self.lights.clear();
-    }
 }
 
 pub struct HDRILight {
     pub color: Color,
     pub intensity: f64,
+    pub map: Option<EnvironmentMap>,
 }
 
 impl HDRILight {
     pub fn new(color: Color, intensity: f64) -> Self {
-        Self { color, intensity }
+        Self { color, intensity, map: None }
+    }
+
+    // `color` tints the panorama when there is one
+    pub fn with_map(color: Color, intensity: f64, map: EnvironmentMap) -> Self {
+        Self { color, intensity, map: Some(map) }
     }
 
     pub fn get_hdr_value(&self, dir: Vec3) -> HDR {
-        let hdr = self.color * self.intensity;
-        hdr
+        let radiance = self.color * self.intensity;
+        match &self.map {
+            Some(map) => radiance.mix(map.lookup(dir)),
+            None => radiance,
+        }
+    }
+
+    // importance sampled by the luminance of the map, uniform over the sphere without one
+    pub fn sample(&self) -> Option<LightSample> {
+        let (wi, pdf) = match &self.map {
+            Some(map) => map.sample()?,
+            None => sampling::uniform_sphere(),
+        };
+        Some(LightSample {
+            wi,
+            distance: f64::INFINITY,
+            radiance: self.get_hdr_value(wi),
+            pdf,
+            delta: false,
+        })
+    }
+
+    pub fn pdf(&self, dir: Vec3) -> f64 {
+        match &self.map {
+            Some(map) => map.pdf(dir),
+            None => 1.0 / (4.0 * PI),
+        }
     }
 }
 
 pub struct SunLight {
     pub direction: Vec3,
+    // angular diameter of the sun disk in radians
+    pub angle: f64,
     pub intensity: f64,
     pub color: Color,
 }
 
 impl SunLight {
-    pub fn new(direction: Vec3, intensity: f64, color: Color) -> Self {
+    pub fn new(direction: Vec3, angle: f64, intensity: f64, color: Color) -> Self {
         let direction = direction.normalize();
-        Self { direction, intensity, color }
+        Self { direction, angle, intensity, color }
+    }
+
+    // jittering the direction over the disk softens shadows, `intensity` stays the irradiance
+    pub fn sample(&self) -> LightSample {
+        let wi = if self.angle > 0.0 {
+            sampling::uniform_cone(self.direction * (-1.0), (self.angle / 2.0).cos()).0
+        } else {
+            self.direction * (-1.0)
+        };
+        LightSample {
+            wi,
+            distance: f64::INFINITY,
+            radiance: self.color * self.intensity,
+            pdf: 1.0,
+            delta: true,
+        }
+    }
+}
+
+// uniform point on the disk a sphere of radius `size` around `origin` shows to `point`. Point
+// and spot lights stay delta lights: bsdf samples never hit them, so the sample keeps pdf 1 and
+// the inverse-square falloff of the center instead of a solid angle density
+fn silhouette_point(origin: Point3, size: f64, point: Point3) -> Point3 {
+    let axis = point - origin;
+    if size == 0.0 || axis.length_squared() == 0.0 {
+        return origin;
     }
+    origin + sampling::local_to_world(sampling::concentric_disk().0, axis.normalize()) * size
 }
 
 pub struct PointLight {
@@ -66,21 +163,78 @@ impl PointLight {
     pub fn new(origin: Point3, size: f64, intensity: f64, color: Color) -> Self {
         Self { origin, size, intensity, color }
     }
+
+    // `size` is the radius of the emitting sphere, jittering the position over its silhouette
+    // softens shadows
+    pub fn sample(&self, point: Point3) -> Option<LightSample> {
+        let position = silhouette_point(self.origin, self.size, point);
+        let to_light = position - point;
+        let distance = to_light.length();
+        if distance == 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            wi: to_light / distance,
+            distance,
+            radiance: self.color * (self.intensity / (distance * distance)),
+            pdf: 1.0,
+            delta: true,
+        })
+    }
 }
 
 pub struct SpotLight {
     pub origin: Point3,
     pub size: f64,
     pub direction: Vec3,
-    pub focal_length: f64,
+    // half angle of the cone in radians
+    pub angle: f64,
+    // fraction of the cone over which the light fades out towards its edge
+    pub blend: f64,
     pub intensity: f64,
     pub color: Color,
 }
 
 impl SpotLight {
-    pub fn new(origin: Point3, size: f64, direction: Vec3, focal_length: f64, intensity: f64, color: Color) -> Self {
+    pub fn new(origin: Point3, size: f64, direction: Vec3, angle: f64, blend: f64, intensity: f64, color: Color) -> Self {
         let direction = direction.normalize();
-        Self { origin, size, direction, focal_length, intensity, color }
+        Self { origin, size, direction, angle, blend, intensity, color }
+    }
+
+    // smooth falloff from full intensity inside the blend region to zero at the cone edge
+    pub fn falloff(&self, dir: Vec3) -> f64 {
+        let cos_theta = dir * self.direction;
+        let cos_outer = self.angle.cos();
+        let cos_inner = (self.angle * (1.0 - self.blend)).cos();
+        if cos_theta <= cos_outer {
+            return 0.0;
+        }
+        if cos_theta >= cos_inner {
+            return 1.0;
+        }
+        let t = (cos_theta - cos_outer) / (cos_inner - cos_outer);
+        t * t * (3.0 - 2.0 * t)
+    }
+
+    pub fn sample(&self, point: Point3) -> Option<LightSample> {
+        let position = silhouette_point(self.origin, self.size, point);
+        let to_light = position - point;
+        let distance = to_light.length();
+        if distance == 0.0 {
+            return None;
+        }
+        let wi = to_light / distance;
+        let falloff = self.falloff(wi * (-1.0));
+        if falloff == 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            wi,
+            distance,
+            radiance: self.color * (self.intensity * falloff / (distance * distance)),
+            pdf: 1.0,
+            delta: true,
+        })
     }
 }
 
@@ -97,12 +251,402 @@ impl AreaLight {
         Self { origin, edge_x, edge_y, intensity, color }
     }
 
-    pub fn vertices(&self) -> [Vec3; 4] {
-        [
-            self.origin,
-            self.origin + self.edge_x,
-            self.origin + self.edge_x + self.edge_y,
-            self.origin + self.edge_y,
-        ]
+    pub fn area(&self) -> f64 {
+        self.edge_x.cross(&self.edge_y).length()
+    }
+
+    // distance to the emitting side along `ray` and the solid angle density `sample` would
+    // have produced that direction with
+    pub fn hit(&self, ray: &Ray, t_max: f64) -> Option<(f64, f64)> {
+        let cross = self.edge_x.cross(&self.edge_y);
+        let normal = cross.normalize();
+        let cos_light = -(ray.direction * normal);
+        if cos_light <= 0.0 {
+            return None;
+        }
+        let t = ((self.origin - ray.origin) * normal) / (ray.direction * normal);
+        if t <= SHADOW_EPSILON || t >= t_max {
+            return None;
+        }
+        // coordinates of the hit point along both edges
+        let local = ray.at(t) - self.origin;
+        let u = local.cross(&self.edge_y) * cross / cross.length_squared();
+        let v = self.edge_x.cross(&local) * cross / cross.length_squared();
+        if !(0.0..=1.0).contains(&u) || !(0.0..=1.0).contains(&v) {
+            return None;
+        }
+        Some((t, t * t / (cos_light * self.area())))
+    }
+
+    // the rectangle emits `intensity` as radiance from the side edge_x x edge_y points to
+    pub fn sample(&self, point: Point3) -> Option<LightSample> {
+        let position = self.origin + self.edge_x * rand_f64() + self.edge_y * rand_f64();
+        let normal = self.edge_x.cross(&self.edge_y).normalize();
+        let to_light = position - point;
+        let distance = to_light.length();
+        if distance == 0.0 {
+            return None;
+        }
+        let wi = to_light / distance;
+        let cos_light = -(wi * normal);
+        if cos_light <= 0.0 {
+            return None;
+        }
+        // uniform over the area, converted to a density over solid angle
+        Some(LightSample {
+            wi,
+            distance,
+            radiance: self.color * self.intensity,
+            pdf: distance * distance / (cos_light * self.area()),
+            delta: false,
+        })
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SerializationEnvironment {
+    #[serde(default = "default_color")]
+    color: (f64, f64, f64),
+    #[serde(default = "default_intensity")]
+    intensity: f64,
+    // equirectangular .hdr or .exr panorama
+    map: Option<PathBuf>,
+    // degrees around the vertical axis
+    #[serde(default)]
+    rotation: f64,
+}
+
+impl SerializationEnvironment {
+    // map paths are written relative to the scene file
+    pub fn resolve_path(&mut self, scene_dir: &Path) {
+        if let Some(map) = &mut self.map {
+            *map = scene_dir.join(&*map);
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SerializationSunLight {
+    direction: (f64, f64, f64),
+    // angular diameter in degrees, 0 for hard shadows
+    #[serde(default)]
+    angle: f64,
+    color: (f64, f64, f64),
+    #[serde(default = "default_intensity")]
+    intensity: f64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SerializationPointLight {
+    origin: (f64, f64, f64),
+    #[serde(default)]
+    size: f64,
+    color: (f64, f64, f64),
+    #[serde(default = "default_intensity")]
+    intensity: f64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SerializationSpotLight {
+    origin: (f64, f64, f64),
+    #[serde(default)]
+    size: f64,
+    direction: (f64, f64, f64),
+    // half angle of the cone in degrees
+    angle: f64,
+    #[serde(default)]
+    blend: f64,
+    color: (f64, f64, f64),
+    #[serde(default = "default_intensity")]
+    intensity: f64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SerializationAreaLight {
+    origin: (f64, f64, f64),
+    edge_x: (f64, f64, f64),
+    edge_y: (f64, f64, f64),
+    color: (f64, f64, f64),
+    #[serde(default = "default_intensity")]
+    intensity: f64,
+}
+
+fn default_intensity() -> f64 {
+    1.0
+}
+
+fn default_color() -> (f64, f64, f64) {
+    (1.0, 1.0, 1.0)
+}
+
+fn check_direction(field: &'static str, direction: (f64, f64, f64)) -> Result<(), FieldError> {
+    let direction: Vec3 = direction.into();
+    if direction.length_squared().is_normal() {
+        Ok(())
+    } else {
+        Err(FieldError::new(field, "direction must not be zero"))
+    }
+}
+
+impl Validate for SerializationEnvironment {
+    fn validate(&self) -> Result<(), FieldError> {
+        check_color("color", self.color)?;
+        check_non_negative("intensity", self.intensity)?;
+        if !self.rotation.is_finite() {
+            return Err(FieldError::new("rotation", "rotation must be a finite number"));
+        }
+        Ok(())
+    }
+}
+
+impl Validate for SerializationSunLight {
+    fn validate(&self) -> Result<(), FieldError> {
+        check_direction("direction", self.direction)?;
+        if !(0.0..=180.0).contains(&self.angle) {
+            return Err(FieldError::new(
+                "angle",
+                "angle must be between 0 and 180 degrees",
+            ));
+        }
+        check_color("color", self.color)?;
+        check_non_negative("intensity", self.intensity)
+    }
+}
+
+impl Validate for SerializationPointLight {
+    fn validate(&self) -> Result<(), FieldError> {
+        check_non_negative("size", self.size)?;
+        check_color("color", self.color)?;
+        check_non_negative("intensity", self.intensity)
+    }
+}
+
+impl Validate for SerializationSpotLight {
+    fn validate(&self) -> Result<(), FieldError> {
+        check_non_negative("size", self.size)?;
+        check_direction("direction", self.direction)?;
+        if !(self.angle > 0.0 && self.angle <= 180.0) {
+            return Err(FieldError::new(
+                "angle",
+                "angle must be between 0 and 180 degrees",
+            ));
+        }
+        check_unit("blend", self.blend)?;
+        check_color("color", self.color)?;
+        check_non_negative("intensity", self.intensity)
+    }
+}
+
+impl Validate for SerializationAreaLight {
+    fn validate(&self) -> Result<(), FieldError> {
+        let edge_x: Vec3 = self.edge_x.into();
+        if !edge_x.cross(&self.edge_y.into()).length_squared().is_normal() {
+            return Err(FieldError::new(
+                "edge_y",
+                "edges must not be zero or parallel",
+            ));
+        }
+        check_color("color", self.color)?;
+        check_non_negative("intensity", self.intensity)
+    }
+}
+
+impl TryFrom<&SerializationEnvironment> for HDRILight {
+    type Error = RaytError;
+
+    fn try_from(value: &SerializationEnvironment) -> Result<Self, RaytError> {
+        Ok(match &value.map {
+            Some(path) => Self::with_map(
+                value.color.into(),
+                value.intensity,
+                EnvironmentMap::load(path, value.rotation.to_radians())?,
+            ),
+            None => Self::new(value.color.into(), value.intensity),
+        })
+    }
+}
+
+impl From<&SerializationSunLight> for SunLight {
+    fn from(value: &SerializationSunLight) -> Self {
+        Self::new(
+            value.direction.into(),
+            value.angle.to_radians(),
+            value.intensity,
+            value.color.into(),
+        )
+    }
+}
+
+impl From<&SerializationPointLight> for PointLight {
+    fn from(value: &SerializationPointLight) -> Self {
+        Self::new(value.origin.into(), value.size, value.intensity, value.color.into())
+    }
+}
+
+impl From<&SerializationSpotLight> for SpotLight {
+    fn from(value: &SerializationSpotLight) -> Self {
+        Self::new(
+            value.origin.into(),
+            value.size,
+            value.direction.into(),
+            value.angle.to_radians(),
+            value.blend,
+            value.intensity,
+            value.color.into(),
+        )
+    }
+}
+
+impl From<&SerializationAreaLight> for AreaLight {
+    fn from(value: &SerializationAreaLight) -> Self {
+        Self::new(
+            value.origin.into(),
+            value.edge_x.into(),
+            value.edge_y.into(),
+            value.intensity,
+            value.color.into(),
+        )
+    }
+}
+
+// every surface sharing an emissive material, one of them is picked in proportion to its area
+// so the whole group is sampled uniformly by area
+pub struct EmissiveLight {
+    pub radiance: HDR,
+    surfaces: Vec<Arc<dyn Surface>>,
+    areas: Distribution1D,
+    area: f64,
+    // addresses of the surfaces, to recognise them in hit records
+    members: HashSet<usize>,
+}
+
+fn address<T: ?Sized>(object: *const T) -> usize {
+    object as *const () as usize
+}
+
+impl EmissiveLight {
+    pub fn new(radiance: HDR, surfaces: Vec<Arc<dyn Surface>>) -> Self {
+        let areas: Vec<f64> = surfaces.iter().map(|s| s.area()).collect();
+        let area = areas.iter().sum();
+        let members = surfaces.iter().map(|s| address(Arc::as_ptr(s))).collect();
+        Self { radiance, surfaces, areas: Distribution1D::new(areas), area, members }
+    }
+
+    pub fn area(&self) -> f64 {
+        self.area
+    }
+
+    pub fn contains(&self, object: &dyn Hittable) -> bool {
+        self.members.contains(&address(object))
+    }
+
+    // solid angle density `sample` gives a point `distance` away seen at `cos_light`
+    pub fn pdf(&self, distance: f64, cos_light: f64) -> f64 {
+        distance * distance / (cos_light * self.area())
+    }
+
+    // only the outward side emits, as for `Emissive` materials
+    pub fn sample(&self, point: Point3, time: f64) -> Option<LightSample> {
+        if self.area() <= 0.0 {
+            return None;
+        }
+        let (_, _, index) = self.areas.sample(rand_f64());
+        let (position, normal) = self.surfaces[index].sample_point(time);
+        let to_light = position - point;
+        let distance = to_light.length();
+        if distance == 0.0 {
+            return None;
+        }
+        let wi = to_light / distance;
+        let cos_light = -(wi * normal);
+        if cos_light <= 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            wi,
+            distance,
+            radiance: self.radiance,
+            pdf: self.pdf(distance, cos_light),
+            delta: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{AreaLight, EmissiveLight, PointLight};
+    use crate::hit::Surface;
+    use crate::material::{Diffuse, Material};
+    use crate::maths::{Color, Vec3};
+    use crate::objects::{Plane, Sphere};
+    use crate::random;
+
+    #[test]
+    fn point_light_samples_its_silhouette() {
+        random::reseed(3);
+        let light = PointLight::new(Vec3::new(0.0, 2.0, 0.0), 0.5, 1.0, Vec3::new(1.0, 1.0, 1.0));
+        let point = Vec3::new(0.0, 0.0, 0.0);
+        for _ in 0..1000 {
+            let sample = light.sample(point).unwrap();
+            let position = point + sample.wi * sample.distance;
+            // on the disk facing the point, never on the far side of the sphere
+            assert!((position.y - 2.0).abs() < 1e-9, "{:?}", position);
+            assert!((position - light.origin).length() <= 0.5 + 1e-9);
+            assert_eq!(sample.pdf, 1.0);
+        }
+    }
+
+    #[test]
+    fn area_light_pdf_matches_solid_angle() {
+        random::reseed(5);
+        // a 2x2 square one unit above the origin, facing down
+        let light = AreaLight::new(
+            Vec3::new(-1.0, 1.0, -1.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 2.0),
+            1.0,
+            Vec3::new(1.0, 1.0, 1.0),
+        );
+        let n = 200000;
+        let mut solid_angle = 0.0;
+        for _ in 0..n {
+            solid_angle += 1.0 / light.sample(Vec3::new(0.0, 0.0, 0.0)).unwrap().pdf;
+        }
+        solid_angle /= n as f64;
+        // 4 asin(ab / sqrt((a^2 + 4d^2)(b^2 + 4d^2))) for an a x b rectangle at distance d
+        let expected = 4.0 * 0.5f64.asin();
+        assert!((solid_angle - expected).abs() < 0.01 * expected, "{}", solid_angle);
+    }
+
+    #[test]
+    fn emissive_light_pdf_matches_solid_angle() {
+        random::reseed(9);
+        let material: Arc<dyn Material> = Arc::new(Diffuse::new(Color::new(0.5, 0.5, 0.5)));
+        // a sphere of radius 1 three units away and a 2x2 square facing the origin from below
+        let sphere: Arc<dyn Surface> =
+            Arc::new(Sphere::new(Vec3::new(0.0, 0.0, -3.0), 1.0, material.clone()));
+        let square: Arc<dyn Surface> = Arc::new(Plane::new(
+            Vec3::new(-1.0, 1.0, -1.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 2.0),
+            material,
+        ));
+        let light = EmissiveLight::new(Vec3::new(1.0, 1.0, 1.0), vec![sphere, square]);
+        assert!((light.area() - (4.0 * std::f64::consts::PI + 4.0)).abs() < 1e-9);
+
+        let n = 400000;
+        let mut solid_angle = 0.0;
+        for _ in 0..n {
+            if let Some(sample) = light.sample(Vec3::new(0.0, 0.0, 0.0), 0.0) {
+                solid_angle += 1.0 / sample.pdf;
+            }
+        }
+        solid_angle /= n as f64;
+        // the cap of the sphere facing the origin plus the square
+        let cap = 2.0 * std::f64::consts::PI * (1.0 - (8.0f64 / 9.0).sqrt());
+        let expected = cap + 4.0 * 0.5f64.asin();
+        assert!((solid_angle - expected).abs() < 0.01 * expected, "{}", solid_angle);
     }
 }
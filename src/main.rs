@@ -1,19 +1,30 @@
-use cli::draw;
-use cli::init;
-
+mod aabb;
+mod accumulator;
+mod bvh;
 mod camera;
 mod cli;
 mod const_vars;
+mod environment;
+mod error;
 mod hit;
 mod light;
+mod material;
 mod maths;
 mod objects;
+mod output;
+mod preview;
+mod random;
 mod ray;
 mod renderer;
+mod sampling;
 mod shaders;
+mod texture;
+mod tonemap;
 mod bsdf;
 
 fn main() {
-    let ctx = init();
-    draw(ctx);
+    if let Err(err) = cli::execute_args() {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
+    }
 }
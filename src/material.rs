@@ -0,0 +1,805 @@
+use core::f64::consts::PI;
+use std::{collections::HashMap, sync::Arc};
+
+use serde::Deserialize;
+
+use crate::{
+    bsdf::BSDF,
+    error::{check_color, check_non_negative, check_unit, FieldError, Validate},
+    hit::HitRecord,
+    maths::{Color, Vec3},
+    random::rand_f64,
+    sampling,
+    texture::{Parameter, SerializationParameter, Texture, TextureLibrary},
+};
+
+pub struct BsdfSample {
+    pub wi: Vec3,
+    pub f: Color,
+    pub pdf: f64,
+    // sampled from a delta lobe, `eval` and `pdf` cannot reproduce it
+    pub specular: bool,
+}
+
+// `wo` points back along the incoming ray and `wi` towards the light, both away from
+// the surface; values returned by `eval` and `sample` do not include the cosine term
+pub trait Material: Send + Sync {
+    fn sample(&self, wo: Vec3, rec: &HitRecord) -> Option<BsdfSample>;
+    fn eval(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> Color;
+    fn pdf(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> f64;
+
+    // bends the shading normal for detail the geometry does not have, called once per hit
+    // before the material is sampled or evaluated
+    fn perturb_normal(&self, _rec: &mut HitRecord) {}
+
+    // radiance given off towards the ray that found `rec`
+    fn emitted(&self, _rec: &HitRecord) -> Color {
+        Color::new(0.0, 0.0, 0.0)
+    }
+}
+
+fn default_map_strength() -> f64 {
+    1.0
+}
+
+fn default_emission_strength() -> f64 {
+    1.0
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SerializationMaterial {
+    name: String,
+    #[serde(flatten)]
+    kind: SerializationMaterialKind,
+    // tangent space normals in a texture loaded with `linear = true`
+    normal_map: Option<String>,
+    // heights read from the brightness of a texture
+    bump_map: Option<String>,
+    // tilts normal map directions, and is the height of a white bump in scene units
+    #[serde(default = "default_map_strength")]
+    map_strength: f64,
+    // radiance given off by the outward side, objects using it are sampled as lights
+    emission: Option<(f64, f64, f64)>,
+    #[serde(default = "default_emission_strength")]
+    emission_strength: f64,
+}
+
+type SerializationColor = SerializationParameter<(f64, f64, f64)>;
+type SerializationScalar = SerializationParameter<f64>;
+
+fn default_roughness() -> SerializationScalar {
+    SerializationParameter::Constant(0.0)
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type")]
+enum SerializationMaterialKind {
+    Diffuse {
+        color: SerializationColor,
+    },
+    Standard {
+        color: SerializationColor,
+        roughness: SerializationScalar,
+        reflectivity: SerializationScalar,
+    },
+    Dielectric {
+        ior: f64,
+        #[serde(default = "default_roughness")]
+        roughness: SerializationScalar,
+    },
+}
+
+impl SerializationMaterial {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // None when the material does not glow
+    pub fn emission(&self) -> Option<Color> {
+        let emission = Color::from(self.emission?) * self.emission_strength;
+        (emission.max_component() > 0.0).then_some(emission)
+    }
+
+    // fields naming a [[Texture]] instead of holding a constant
+    pub fn normal_map(&self) -> Option<&str> {
+        self.normal_map.as_deref()
+    }
+
+    pub fn textures(&self) -> Vec<(&'static str, &str)> {
+        let fields = match &self.kind {
+            SerializationMaterialKind::Diffuse { color } => vec![("color", color.texture())],
+            SerializationMaterialKind::Standard {
+                color,
+                roughness,
+                reflectivity,
+            } => vec![
+                ("color", color.texture()),
+                ("roughness", roughness.texture()),
+                ("reflectivity", reflectivity.texture()),
+            ],
+            SerializationMaterialKind::Dielectric { roughness, .. } => {
+                vec![("roughness", roughness.texture())]
+            }
+        };
+        fields
+            .into_iter()
+            .chain([
+                ("normal_map", self.normal_map.as_deref()),
+                ("bump_map", self.bump_map.as_deref()),
+            ])
+            .filter_map(|(field, name)| Some((field, name?)))
+            .collect()
+    }
+}
+
+fn check_color_parameter(
+    field: &'static str,
+    color: &SerializationColor,
+) -> Result<(), FieldError> {
+    color.constant().map_or(Ok(()), |c| check_color(field, c))
+}
+
+fn check_unit_parameter(
+    field: &'static str,
+    value: &SerializationScalar,
+) -> Result<(), FieldError> {
+    value.constant().map_or(Ok(()), |v| check_unit(field, v))
+}
+
+impl Validate for SerializationMaterial {
+    fn validate(&self) -> Result<(), FieldError> {
+        if self.normal_map.is_some() && self.bump_map.is_some() {
+            return Err(FieldError::new(
+                "bump_map",
+                "set either a normal map or a bump map, not both",
+            ));
+        }
+        check_non_negative("map_strength", self.map_strength)?;
+        if let Some(emission) = self.emission {
+            check_color("emission", emission)?;
+        }
+        check_non_negative("emission_strength", self.emission_strength)?;
+
+        match &self.kind {
+            SerializationMaterialKind::Diffuse { color } => check_color_parameter("color", color),
+            SerializationMaterialKind::Standard {
+                color,
+                roughness,
+                reflectivity,
+            } => {
+                check_color_parameter("color", color)?;
+                check_unit_parameter("roughness", roughness)?;
+                check_unit_parameter("reflectivity", reflectivity)
+            }
+            SerializationMaterialKind::Dielectric { ior, roughness } => {
+                if !(ior.is_finite() && *ior > 0.0) {
+                    return Err(FieldError::new(
+                        "ior",
+                        "index of refraction must be positive",
+                    ));
+                }
+                check_unit_parameter("roughness", roughness)
+            }
+        }
+    }
+}
+
+fn from_serialization(
+    value: &SerializationMaterial,
+    textures: &TextureLibrary,
+) -> Arc<dyn Material> {
+    let mut material = from_serialization_kind(&value.kind, textures);
+    let detail = match (&value.normal_map, &value.bump_map) {
+        (Some(name), _) => Some(SurfaceDetail::NormalMap(textures.get(name))),
+        (None, Some(name)) => Some(SurfaceDetail::BumpMap(textures.get(name))),
+        (None, None) => None,
+    };
+    if let Some(detail) = detail {
+        material = Arc::new(Detailed::new(material, detail, value.map_strength));
+    }
+    if let Some(emission) = value.emission() {
+        material = Arc::new(Emissive::new(material, emission));
+    }
+    material
+}
+
+fn from_serialization_kind(
+    kind: &SerializationMaterialKind,
+    textures: &TextureLibrary,
+) -> Arc<dyn Material> {
+    match kind {
+        SerializationMaterialKind::Diffuse { color } => {
+            Arc::new(Diffuse::new(color.resolve(textures)))
+        }
+        SerializationMaterialKind::Standard {
+            color,
+            roughness,
+            reflectivity,
+        } => Arc::new(Standard::new(
+            color.resolve(textures),
+            roughness.resolve(textures),
+            reflectivity.resolve(textures),
+        )),
+        SerializationMaterialKind::Dielectric { ior, roughness } => {
+            Arc::new(Dielectric::new(*ior, roughness.resolve(textures)))
+        }
+    }
+}
+
+pub struct MaterialLibrary {
+    materials: HashMap<String, Arc<dyn Material>>,
+}
+
+impl MaterialLibrary {
+    pub fn new<'a>(
+        materials: impl IntoIterator<Item = &'a SerializationMaterial>,
+        textures: &TextureLibrary,
+    ) -> Self {
+        Self {
+            materials: materials
+                .into_iter()
+                .map(|material| {
+                    (
+                        material.name.clone(),
+                        from_serialization(material, textures),
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    // names are checked against the library when the scene is validated
+    pub fn get(&self, name: &str) -> Arc<dyn Material> {
+        self.materials
+            .get(name)
+            .unwrap_or_else(|| panic!("unknown material `{}`", name))
+            .clone()
+    }
+}
+
+pub enum SurfaceDetail {
+    NormalMap(Arc<dyn Texture>),
+    BumpMap(Arc<dyn Texture>),
+}
+
+// step in texture coordinates for the finite differences of a bump map
+const BUMP_DELTA: f64 = 0.0005;
+
+// another material with its shading normal perturbed by a normal or bump map
+pub struct Detailed {
+    material: Arc<dyn Material>,
+    detail: SurfaceDetail,
+    strength: f64,
+}
+
+impl Detailed {
+    pub fn new(material: Arc<dyn Material>, detail: SurfaceDetail, strength: f64) -> Self {
+        Self {
+            material,
+            detail,
+            strength,
+        }
+    }
+
+    // orthonormal tangent and bitangent around the shading normal following dp/du and dp/dv
+    fn tangent_frame(rec: &HitRecord) -> Option<(Vec3, Vec3)> {
+        let n = rec.normal;
+        let t = rec.dpdu - n * (n * rec.dpdu);
+        if t.length_squared() < 1e-24 {
+            return None;
+        }
+        let t = t.normalize();
+        let b = rec.dpdv - n * (n * rec.dpdv) - t * (t * rec.dpdv);
+        if b.length_squared() < 1e-24 {
+            return None;
+        }
+        Some((t, b.normalize()))
+    }
+
+    fn height(texture: &dyn Texture, uv: (f64, f64), point: Vec3) -> f64 {
+        texture.value(uv, point).luminance()
+    }
+}
+
+impl Material for Detailed {
+    fn sample(&self, wo: Vec3, rec: &HitRecord) -> Option<BsdfSample> {
+        self.material.sample(wo, rec)
+    }
+
+    fn eval(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> Color {
+        self.material.eval(wo, wi, rec)
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> f64 {
+        self.material.pdf(wo, wi, rec)
+    }
+
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        self.material.emitted(rec)
+    }
+
+    fn perturb_normal(&self, rec: &mut HitRecord) {
+        self.material.perturb_normal(rec);
+        // maps describe the outside of the surface, work there and turn the result back to the
+        // side the ray came from at the end
+        let side = if rec.is_entering() { 1.0 } else { -1.0 };
+        let n = rec.normal * side;
+        let normal = match &self.detail {
+            SurfaceDetail::NormalMap(texture) => {
+                let (t, b) = match Self::tangent_frame(rec) {
+                    Some(frame) => frame,
+                    None => return,
+                };
+                // colors map [0, 1] onto [-1, 1], the flat color (0.5, 0.5, 1) leaves n alone
+                let c = texture.value(rec.uv, rec.object.point) * 2.0 - Vec3::new(1.0, 1.0, 1.0);
+                t * (c.x * self.strength) + b * (c.y * self.strength) + n * c.z
+            }
+            SurfaceDetail::BumpMap(texture) => {
+                // displace the surface outwards and differentiate the displaced position
+                let (u, v) = rec.uv;
+                let height = Self::height(texture.as_ref(), rec.uv, rec.object.point);
+                let height_u = Self::height(
+                    texture.as_ref(),
+                    (u + BUMP_DELTA, v),
+                    rec.object.point + rec.object.dpdu * BUMP_DELTA,
+                );
+                let height_v = Self::height(
+                    texture.as_ref(),
+                    (u, v + BUMP_DELTA),
+                    rec.object.point + rec.object.dpdv * BUMP_DELTA,
+                );
+                let dpdu = rec.dpdu + n * ((height_u - height) / BUMP_DELTA * self.strength);
+                let dpdv = rec.dpdv + n * ((height_v - height) / BUMP_DELTA * self.strength);
+                let normal = dpdu.cross(&dpdv);
+                // the uv winding may disagree with the outward normal
+                if normal * n < 0.0 {
+                    normal * (-1.0)
+                } else {
+                    normal
+                }
+            }
+        };
+        if normal.length_squared().is_normal() {
+            rec.normal = normal.normalize() * side;
+        }
+    }
+}
+
+// another material glowing with `radiance` on the outward side of the surface
+pub struct Emissive {
+    material: Arc<dyn Material>,
+    radiance: Color,
+}
+
+impl Emissive {
+    pub fn new(material: Arc<dyn Material>, radiance: Color) -> Self {
+        Self { material, radiance }
+    }
+}
+
+impl Material for Emissive {
+    fn sample(&self, wo: Vec3, rec: &HitRecord) -> Option<BsdfSample> {
+        self.material.sample(wo, rec)
+    }
+
+    fn eval(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> Color {
+        self.material.eval(wo, wi, rec)
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> f64 {
+        self.material.pdf(wo, wi, rec)
+    }
+
+    fn perturb_normal(&self, rec: &mut HitRecord) {
+        self.material.perturb_normal(rec);
+    }
+
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        if rec.is_entering() {
+            self.radiance
+        } else {
+            Color::new(0.0, 0.0, 0.0)
+        }
+    }
+}
+
+// Lambertian reflector
+pub struct Diffuse {
+    color: Parameter<Color>,
+}
+
+impl Diffuse {
+    pub fn new(color: impl Into<Parameter<Color>>) -> Self {
+        Self {
+            color: color.into(),
+        }
+    }
+}
+
+impl Material for Diffuse {
+    fn sample(&self, wo: Vec3, rec: &HitRecord) -> Option<BsdfSample> {
+        let (wi, pdf) = sampling::cosine_hemisphere(rec.normal);
+        if wi * rec.normal <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            f: self.eval(wo, wi, rec),
+            pdf,
+            specular: false,
+        })
+    }
+
+    fn eval(&self, _wo: Vec3, wi: Vec3, rec: &HitRecord) -> Color {
+        if wi * rec.normal <= 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        self.color.value(rec) / PI
+    }
+
+    fn pdf(&self, _wo: Vec3, wi: Vec3, rec: &HitRecord) -> f64 {
+        (wi * rec.normal).max(0.0) / PI
+    }
+}
+
+// Cook–Torrance microfacet reflection over a Lambertian base
+pub struct Standard {
+    color: Parameter<Color>,
+    roughness: Parameter<f64>,
+    reflectivity: Parameter<f64>,
+}
+
+impl Standard {
+    pub fn new(
+        color: impl Into<Parameter<Color>>,
+        roughness: impl Into<Parameter<f64>>,
+        reflectivity: impl Into<Parameter<f64>>,
+    ) -> Self {
+        Self {
+            color: color.into(),
+            roughness: roughness.into(),
+            reflectivity: reflectivity.into(),
+        }
+    }
+
+    // textures may stray outside the range a constant is validated against
+    fn roughness(&self, rec: &HitRecord) -> f64 {
+        self.roughness.value(rec).clamp(0.0, 1.0)
+    }
+
+    fn reflectivity(&self, rec: &HitRecord) -> f64 {
+        self.reflectivity.value(rec).clamp(0.0, 1.0)
+    }
+
+    // chance of sampling the specular lobe instead of the diffuse one
+    fn specular_probability(&self, rec: &HitRecord) -> f64 {
+        0.25 + 0.75 * self.reflectivity(rec)
+    }
+}
+
+impl Material for Standard {
+    fn sample(&self, wo: Vec3, rec: &HitRecord) -> Option<BsdfSample> {
+        let n = rec.normal;
+        if wo * n <= 0.0 {
+            return None;
+        }
+        let wi = if rand_f64() < self.specular_probability(rec) {
+            let (h, _) = sampling::ggx_vndf(n, wo, BSDF::alpha(self.roughness(rec)));
+            (wo * (-1.0)).reflect(h)
+        } else {
+            sampling::cosine_hemisphere(n).0
+        };
+        if wi * n <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            f: self.eval(wo, wi, rec),
+            pdf: self.pdf(wo, wi, rec),
+            specular: false,
+        })
+    }
+
+    fn eval(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> Color {
+        BSDF::cook_torrance_brdf(
+            self.roughness(rec),
+            self.reflectivity(rec),
+            wo,
+            wi,
+            rec.normal,
+            self.color.value(rec),
+        )
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> f64 {
+        let n = rec.normal;
+        if wo * n <= 0.0 || wi * n <= 0.0 {
+            return 0.0;
+        }
+        let h = (wo + wi).normalize();
+        let alpha = BSDF::alpha(self.roughness(rec));
+        let specular = sampling::ggx_vndf_pdf(n, wo, h, alpha) / (4.0 * (wo * h));
+        let diffuse = (wi * n) / PI;
+        let p = self.specular_probability(rec);
+        p * specular + (1.0 - p) * diffuse
+    }
+}
+
+// glass-like interface between air and a medium of index `ior`, smooth when `roughness` is
+// zero and a GGX microfacet transmission model (Walter et al. 2007) otherwise
+pub struct Dielectric {
+    ior: f64,
+    roughness: Parameter<f64>,
+}
+
+impl Dielectric {
+    pub fn new(ior: f64, roughness: impl Into<Parameter<f64>>) -> Self {
+        Self {
+            ior,
+            roughness: roughness.into(),
+        }
+    }
+
+    fn roughness(&self, rec: &HitRecord) -> f64 {
+        self.roughness.value(rec).clamp(0.0, 1.0)
+    }
+
+    fn is_smooth(&self, rec: &HitRecord) -> bool {
+        self.roughness(rec) == 0.0
+    }
+
+    // transmitted over incident index of refraction as seen from the side `rec.normal` faces
+    fn eta(&self, rec: &HitRecord) -> f64 {
+        if rec.is_entering() {
+            self.ior
+        } else {
+            1.0 / self.ior
+        }
+    }
+
+    fn sample_smooth(&self, wo: Vec3, rec: &HitRecord) -> Option<BsdfSample> {
+        let n = rec.normal;
+        let eta = self.eta(rec);
+        let cos_o = wo * n;
+        let fresnel = BSDF::fresnel_dielectric(cos_o, eta);
+
+        // the weights are divided by the cosine the integrator multiplies back in
+        if rand_f64() < fresnel {
+            let wi = (wo * (-1.0)).reflect(n);
+            Some(BsdfSample {
+                wi,
+                f: Color::new(1.0, 1.0, 1.0) * (fresnel / (wi * n).abs()),
+                pdf: fresnel,
+                specular: true,
+            })
+        } else {
+            let wi = (wo * (-1.0)).refract(n, 1.0 / eta)?;
+            // radiance is compressed into a smaller solid angle when entering a denser medium
+            let f = (1.0 - fresnel) / (eta * eta * (wi * n).abs());
+            Some(BsdfSample {
+                wi,
+                f: Color::new(1.0, 1.0, 1.0) * f,
+                pdf: 1.0 - fresnel,
+                specular: true,
+            })
+        }
+    }
+
+    // microfacet normal between `wo` and `wi` facing `n`, None for degenerate configurations
+    fn half_vector(&self, wo: Vec3, wi: Vec3, n: Vec3, eta: f64) -> Option<Vec3> {
+        let reflect = wi * n > 0.0;
+        let h = if reflect { wo + wi } else { wo + wi * eta };
+        if h.length_squared() == 0.0 {
+            return None;
+        }
+        let h = h.normalize();
+        let h = if h * n < 0.0 { h * (-1.0) } else { h };
+        // both directions have to sit on their own side of the microfacet
+        if reflect != ((wi * h) > 0.0) || wo * h <= 0.0 {
+            return None;
+        }
+        Some(h)
+    }
+}
+
+impl Material for Dielectric {
+    fn sample(&self, wo: Vec3, rec: &HitRecord) -> Option<BsdfSample> {
+        let n = rec.normal;
+        if wo * n <= 0.0 {
+            return None;
+        }
+        if self.is_smooth(rec) {
+            return self.sample_smooth(wo, rec);
+        }
+
+        let eta = self.eta(rec);
+        let (h, _) = sampling::ggx_vndf(n, wo, BSDF::alpha(self.roughness(rec)));
+        let cos_oh = wo * h;
+        if cos_oh <= 0.0 {
+            return None;
+        }
+        let reflect = rand_f64() < BSDF::fresnel_dielectric(cos_oh, eta);
+        let wi = if reflect {
+            (wo * (-1.0)).reflect(h)
+        } else {
+            (wo * (-1.0)).refract(h, 1.0 / eta)?
+        };
+        // tilted microfacets can send the direction to the wrong side of the macro surface
+        if reflect != (wi * n > 0.0) {
+            return None;
+        }
+        let pdf = self.pdf(wo, wi, rec);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            wi,
+            f: self.eval(wo, wi, rec),
+            pdf,
+            specular: false,
+        })
+    }
+
+    fn eval(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> Color {
+        let n = rec.normal;
+        let cos_o = wo * n;
+        let cos_i = wi * n;
+        if self.is_smooth(rec) || cos_o <= 0.0 || cos_i == 0.0 {
+            return Color::new(0.0, 0.0, 0.0);
+        }
+        let eta = self.eta(rec);
+        let h = match self.half_vector(wo, wi, n, eta) {
+            Some(h) => h,
+            None => return Color::new(0.0, 0.0, 0.0),
+        };
+
+        let alpha = BSDF::alpha(self.roughness(rec));
+        let d = BSDF::ggx_distribution(n * h, alpha);
+        let g = BSDF::smith_g1(cos_o, alpha) * BSDF::smith_g1(cos_i.abs(), alpha);
+        let fresnel = BSDF::fresnel_dielectric(wo * h, eta);
+
+        let value = if cos_i > 0.0 {
+            fresnel * d * g / (4.0 * cos_o * cos_i)
+        } else {
+            // the eta^2 of the Jacobian cancels with the radiance scaling across the interface
+            let denom = wo * h + eta * (wi * h);
+            (1.0 - fresnel) * d * g * (wo * h) * (wi * h).abs()
+                / (cos_o * cos_i.abs() * denom * denom)
+        };
+        Color::new(1.0, 1.0, 1.0) * value
+    }
+
+    fn pdf(&self, wo: Vec3, wi: Vec3, rec: &HitRecord) -> f64 {
+        let n = rec.normal;
+        if self.is_smooth(rec) || wo * n <= 0.0 {
+            return 0.0;
+        }
+        let eta = self.eta(rec);
+        let h = match self.half_vector(wo, wi, n, eta) {
+            Some(h) => h,
+            None => return 0.0,
+        };
+
+        let alpha = BSDF::alpha(self.roughness(rec));
+        let pdf_h = sampling::ggx_vndf_pdf(n, wo, h, alpha);
+        let fresnel = BSDF::fresnel_dielectric(wo * h, eta);
+        if wi * n > 0.0 {
+            fresnel * pdf_h / (4.0 * (wo * h))
+        } else {
+            let denom = wo * h + eta * (wi * h);
+            (1.0 - fresnel) * pdf_h * eta * eta * (wi * h).abs() / (denom * denom)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{Detailed, Dielectric, Diffuse, Material, SurfaceDetail};
+    use crate::{
+        hit::Hittable,
+        maths::{Color, Point3, Vec3},
+        objects::{Plane, Sphere},
+        random,
+        ray::Ray,
+        texture::Texture,
+    };
+
+    // brightness rising along u
+    struct Ramp;
+
+    impl Texture for Ramp {
+        fn value(&self, (u, _): (f64, f64), _point: Point3) -> Color {
+            Color::new(u, u, u)
+        }
+    }
+
+    struct Flat(Color);
+
+    impl Texture for Flat {
+        fn value(&self, _uv: (f64, f64), _point: Point3) -> Color {
+            self.0
+        }
+    }
+
+    // the shading normal seen by a ray coming down onto the floor, or up from below for `side` -1
+    fn perturbed_normal(detail: SurfaceDetail, strength: f64, side: f64) -> Vec3 {
+        let base: Arc<dyn Material> = Arc::new(Diffuse::new(Color::new(0.5, 0.5, 0.5)));
+        let material: Arc<dyn Material> = Arc::new(Detailed::new(base, detail, strength));
+        // a floor with u along +x and the outside facing up
+        let plane = Plane::new(
+            Vec3::new(-1.0, 0.0, 1.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, -2.0),
+            material.clone(),
+        );
+        let ray = Ray::new(Vec3::new(0.1, side, 0.2), Vec3::new(0.0, -side, 0.0));
+        let mut rec = plane.get_hit_record(&ray, 1e-4, f64::INFINITY).unwrap();
+        assert!((rec.normal - Vec3::new(0.0, side, 0.0)).length() < 1e-12);
+        material.perturb_normal(&mut rec);
+        rec.normal
+    }
+
+    #[test]
+    fn normal_and_bump_maps_tilt_the_shading_normal() {
+        for side in [1.0, -1.0] {
+            let flat = perturbed_normal(
+                SurfaceDetail::NormalMap(Arc::new(Flat(Color::new(0.5, 0.5, 1.0)))),
+                1.0,
+                side,
+            );
+            assert!((flat - Vec3::new(0.0, side, 0.0)).length() < 1e-12);
+
+            // x of the map follows dp/du, from below the whole tilted normal turns around
+            let tilted = perturbed_normal(
+                SurfaceDetail::NormalMap(Arc::new(Flat(Color::new(0.75, 0.5, 1.0)))),
+                1.0,
+                side,
+            );
+            let expected = Vec3::new(0.5, 1.0, 0.0).normalize() * side;
+            assert!((tilted - expected).length() < 1e-9);
+
+            // a height rising by `strength` across the 2 units of u faces the normal back along -x
+            let bumped = perturbed_normal(SurfaceDetail::BumpMap(Arc::new(Ramp)), 0.5, side);
+            let expected = Vec3::new(-1.0, 4.0, 0.0).normalize() * side;
+            assert!((bumped - expected).length() < 1e-6);
+            let flat = perturbed_normal(SurfaceDetail::BumpMap(Arc::new(Ramp)), 0.0, side);
+            assert!((flat - Vec3::new(0.0, side, 0.0)).length() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn dielectric_conserves_energy() {
+        random::reseed(3);
+        for roughness in [0.0, 0.3, 0.8] {
+            let material = Arc::new(Dielectric::new(1.5, roughness));
+            let sphere = Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, material.clone());
+
+            // entering from outside and leaving from inside at an oblique angle
+            for origin in [Vec3::new(0.3, 0.2, 5.0), Vec3::new(0.3, 0.2, 0.0)] {
+                let ray = Ray::new(origin, Vec3::new(0.05, -0.1, -1.0));
+                let rec = sphere.get_hit_record(&ray, 1e-4, f64::INFINITY).unwrap();
+                let wo = ray.direction * (-1.0);
+                let eta = if rec.is_entering() { 1.5 } else { 1.0 / 1.5 };
+
+                let n = 20000;
+                let mut total = 0.0;
+                for _ in 0..n {
+                    if let Some(s) = material.sample(wo, &rec) {
+                        let mut weight = s.f.y * (s.wi * rec.normal).abs() / s.pdf;
+                        // undo the radiance scaling so the throughput is bounded by one
+                        if s.wi * rec.normal < 0.0 {
+                            weight *= eta * eta;
+                        }
+                        total += weight;
+                    }
+                }
+                let average = total / n as f64;
+                // single scattering microfacets lose some energy, mostly to internal reflection
+                assert!(average <= 1.01, "roughness {}: {}", roughness, average);
+                assert!(average > 0.5, "roughness {}: {}", roughness, average);
+            }
+        }
+    }
+}
@@ -1,10 +1,9 @@
-use std::ops::{Add, Div, Mul, Sub};
-use core::f64::consts::PI;
+use std::ops::{Add, Div, Index, Mul, Sub};
 
 use image::Rgb;
-use rand::{thread_rng, Rng};
 
 pub type Color = Vec3;
+#[allow(clippy::upper_case_acronyms)]
 pub type HDR = Vec3;
 pub type Point3 = Vec3;
 
@@ -39,7 +38,7 @@ impl Vec3 {
     }
 
     pub fn normalize(&self) -> Self {
-        self.clone() / self.length()
+        *self / self.length()
     }
 
     pub fn cross(&self, other: &Vec3) -> Vec3 {
@@ -50,6 +49,31 @@ impl Vec3 {
         }
     }
 
+    pub fn min(&self, other: &Vec3) -> Vec3 {
+        Self::new(self.x.min(other.x), self.y.min(other.y), self.z.min(other.z))
+    }
+
+    pub fn max(&self, other: &Vec3) -> Vec3 {
+        Self::new(self.x.max(other.x), self.y.max(other.y), self.z.max(other.z))
+    }
+
+    // mirror direction of `self` about `normal`
+    pub fn reflect(&self, normal: Vec3) -> Vec3 {
+        *self - normal * (2.0 * (*self * normal))
+    }
+
+    // `self` is a unit vector pointing towards the surface, `normal` faces it and `eta` is
+    // the incident over the transmitted index of refraction; None on total internal reflection
+    pub fn refract(&self, normal: Vec3, eta: f64) -> Option<Vec3> {
+        let cos_i = -(*self * normal);
+        let sin2_t = eta * eta * (1.0 - cos_i * cos_i).max(0.0);
+        if sin2_t >= 1.0 {
+            return None;
+        }
+        let cos_t = (1.0 - sin2_t).sqrt();
+        Some(*self * eta + normal * (eta * cos_i - cos_t))
+    }
+
     pub fn mix(&self, color: Color) -> Color {
         Self::new(
             self.x * color.x,
@@ -66,42 +90,13 @@ impl Vec3 {
         )
     }
 
-    pub fn gamma_correction(&self, gamma: f64) -> Color {
-        Self::new(
-            self.x.powf(1.0/gamma).clamp(0.0, 1.0), 
-            self.y.powf(1.0/gamma).clamp(0.0, 1.0), 
-            self.z.powf(1.0/gamma).clamp(0.0, 1.0),
-        )
+    // relative luminance of a linear Rec. 709 / sRGB color
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.x + 0.7152 * self.y + 0.0722 * self.z
     }
 
-    pub fn rand_hemisphere_dir(norm: Vec3) -> Vec3 {
-        let mut rng = thread_rng();
-        let theta = rng.gen::<f64>() * PI / 2.0;
-        let phi = rng.gen::<f64>() * PI * 2.0;
-        let rand_dir = Vec3::new(theta.sin()*phi.cos(), theta.sin()*phi.sin(), theta.cos());
-
-        let normal = norm.normalize();
-        let mut x = Vec3::new(1.0, 0.0, 0.0);
-        let mut y = Vec3::new(0.0, 1.0, 0.0);
-        let mut z = normal;
-        if normal == Vec3::new(0.0, 0.0, 1.0) {
-            x = Vec3::new(1.0, 0.0, 0.0);
-        }
-        else if normal == Vec3::new(0.0, 0.0, -1.0) {
-            x = Vec3::new(-1.0, 0.0, 0.0);
-        }
-        else {
-            x = Vec3::new(0.0, 0.0, 1.0).cross(&normal).normalize();
-        }
-        y = z.cross(&x).normalize();
-
-        let rotated_dir = Vec3::new(
-            x.x*rand_dir.x + y.x*rand_dir.y + z.x*rand_dir.z, 
-            x.y*rand_dir.x + y.y*rand_dir.y + z.y*rand_dir.z, 
-            x.z*rand_dir.x + y.z*rand_dir.y + z.z*rand_dir.z,
-        ).normalize();
-
-        rotated_dir
+    pub fn max_component(&self) -> f64 {
+        self.x.max(self.y).max(self.z)
     }
 }
 
@@ -179,24 +174,261 @@ impl Div for Vec3 {
     }
 }
 
+impl Index<usize> for Vec3 {
+    type Output = f64;
+
+    fn index(&self, axis: usize) -> &f64 {
+        match axis {
+            0 => &self.x,
+            1 => &self.y,
+            2 => &self.z,
+            _ => panic!("Vec3 index out of range: {}", axis),
+        }
+    }
+}
+
 impl PartialEq for Vec3 {
     fn eq(&self, other: &Self) -> bool {
         self.x == other.x && self.y == other.y && self.z == other.z
     }
 }
 
-impl Into<Rgb<u8>> for Vec3 {
-    fn into(self) -> Rgb<u8> {
+impl From<Vec3> for Rgb<u8> {
+    fn from(value: Vec3) -> Self {
         Rgb([
-            (self.x.clamp(0.0, 0.9999) * 256.0) as u8,
-            (self.y.clamp(0.0, 0.9999) * 256.0) as u8,
-            (self.z.clamp(0.0, 0.9999) * 256.0) as u8,
+            (value.x.clamp(0.0, 0.9999) * 256.0) as u8,
+            (value.y.clamp(0.0, 0.9999) * 256.0) as u8,
+            (value.z.clamp(0.0, 0.9999) * 256.0) as u8,
         ])
     }
 }
 
+// unit quaternion describing a rotation
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quaternion {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub w: f64,
+}
+
+impl Quaternion {
+    pub fn new(x: f64, y: f64, z: f64, w: f64) -> Self {
+        let length = (x * x + y * y + z * z + w * w).sqrt();
+        Self {
+            x: x / length,
+            y: y / length,
+            z: z / length,
+            w: w / length,
+        }
+    }
+
+    pub fn identity() -> Self {
+        Self::new(0.0, 0.0, 0.0, 1.0)
+    }
+
+    pub fn from_axis_angle(axis: Vec3, angle: f64) -> Self {
+        let axis = axis.normalize() * (angle / 2.0).sin();
+        Self::new(axis.x, axis.y, axis.z, (angle / 2.0).cos())
+    }
+
+    // rotates around x, then y, then z, all in radians and about the fixed world axes
+    pub fn from_euler(angles: Vec3) -> Self {
+        Self::from_axis_angle(Vec3::new(0.0, 0.0, 1.0), angles.z)
+            * Self::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), angles.y)
+            * Self::from_axis_angle(Vec3::new(1.0, 0.0, 0.0), angles.x)
+    }
+
+    pub fn conjugate(&self) -> Self {
+        Self::new(-self.x, -self.y, -self.z, self.w)
+    }
+
+    // angle of the rotation in radians
+    pub fn angle(&self) -> f64 {
+        2.0 * self.w.abs().min(1.0).acos()
+    }
+
+    // spherical interpolation along the shorter arc
+    pub fn slerp(&self, other: &Self, t: f64) -> Self {
+        let mut cos_theta =
+            self.x * other.x + self.y * other.y + self.z * other.z + self.w * other.w;
+        let other = if cos_theta < 0.0 {
+            cos_theta = -cos_theta;
+            Self {
+                x: -other.x,
+                y: -other.y,
+                z: -other.z,
+                w: -other.w,
+            }
+        } else {
+            *other
+        };
+        let (a, b) = if cos_theta > 0.9995 {
+            // nearly parallel, fall back to a normalized lerp
+            (1.0 - t, t)
+        } else {
+            let theta = cos_theta.acos();
+            (
+                ((1.0 - t) * theta).sin() / theta.sin(),
+                (t * theta).sin() / theta.sin(),
+            )
+        };
+        Self::new(
+            a * self.x + b * other.x,
+            a * self.y + b * other.y,
+            a * self.z + b * other.z,
+            a * self.w + b * other.w,
+        )
+    }
+}
+
+impl Mul for Quaternion {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        Self::new(
+            self.w * rhs.x + self.x * rhs.w + self.y * rhs.z - self.z * rhs.y,
+            self.w * rhs.y - self.x * rhs.z + self.y * rhs.w + self.z * rhs.x,
+            self.w * rhs.z + self.x * rhs.y - self.y * rhs.x + self.z * rhs.w,
+            self.w * rhs.w - self.x * rhs.x - self.y * rhs.y - self.z * rhs.z,
+        )
+    }
+}
+
+// row-major affine transform acting on column vectors
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Mat4 {
+    pub m: [[f64; 4]; 4],
+}
+
+impl Mat4 {
+    pub fn translation(offset: Vec3) -> Self {
+        Self {
+            m: [
+                [1.0, 0.0, 0.0, offset.x],
+                [0.0, 1.0, 0.0, offset.y],
+                [0.0, 0.0, 1.0, offset.z],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    pub fn scale(factors: Vec3) -> Self {
+        Self {
+            m: [
+                [factors.x, 0.0, 0.0, 0.0],
+                [0.0, factors.y, 0.0, 0.0],
+                [0.0, 0.0, factors.z, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    pub fn rotation(q: Quaternion) -> Self {
+        let Quaternion { x, y, z, w } = q;
+        Self {
+            m: [
+                [
+                    1.0 - 2.0 * (y * y + z * z),
+                    2.0 * (x * y - z * w),
+                    2.0 * (x * z + y * w),
+                    0.0,
+                ],
+                [
+                    2.0 * (x * y + z * w),
+                    1.0 - 2.0 * (x * x + z * z),
+                    2.0 * (y * z - x * w),
+                    0.0,
+                ],
+                [
+                    2.0 * (x * z - y * w),
+                    2.0 * (y * z + x * w),
+                    1.0 - 2.0 * (x * x + y * y),
+                    0.0,
+                ],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.m[j][i];
+            }
+        }
+        Self { m }
+    }
+
+    pub fn transform_point(&self, p: Point3) -> Point3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3],
+            m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3],
+            m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3],
+        )
+    }
+
+    // ignores the translation, for directions and offsets
+    pub fn transform_vector(&self, v: Vec3) -> Vec3 {
+        let m = &self.m;
+        Vec3::new(
+            m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+            m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+            m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z,
+        )
+    }
+}
+
+impl Mul for Mat4 {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[i][k] * rhs.m[k][j]).sum();
+            }
+        }
+        Self { m }
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use super::{Mat4, Quaternion, Vec3};
+
     #[test]
     fn normalize_test() {}
+
+    #[test]
+    fn rotations_agree() {
+        let q = Quaternion::from_euler(Vec3::new(0.3, -1.1, 2.0));
+        let m = Mat4::translation(Vec3::new(1.0, 2.0, 3.0))
+            * Mat4::rotation(q)
+            * Mat4::scale(Vec3::new(2.0, 0.5, -1.0));
+        let inverse = Mat4::scale(Vec3::new(0.5, 2.0, -1.0))
+            * Mat4::rotation(q.conjugate())
+            * Mat4::translation(Vec3::new(-1.0, -2.0, -3.0));
+
+        let p = Vec3::new(0.7, -0.2, 5.0);
+        assert!((inverse.transform_point(m.transform_point(p)) - p).length() < 1e-12);
+
+        // a quarter turn around z takes x to y
+        let quarter = Mat4::rotation(Quaternion::from_euler(Vec3::new(
+            0.0,
+            0.0,
+            0.5 * std::f64::consts::PI,
+        )));
+        let x = quarter.transform_vector(Vec3::new(1.0, 0.0, 0.0));
+        assert!((x - Vec3::new(0.0, 1.0, 0.0)).length() < 1e-12);
+
+        // halfway between no rotation and the quarter turn is an eighth
+        let half = Quaternion::identity().slerp(
+            &Quaternion::from_euler(Vec3::new(0.0, 0.0, 0.5 * std::f64::consts::PI)),
+            0.5,
+        );
+        assert!((half.angle() - 0.25 * std::f64::consts::PI).abs() < 1e-12);
+    }
 }
@@ -0,0 +1,476 @@
+use std::sync::Arc;
+
+use serde::Deserialize;
+
+use crate::aabb::Aabb;
+use crate::error::{FieldError, Validate};
+use crate::hit::{HitRecord, Hittable, Surface};
+use crate::maths::{Mat4, Point3, Quaternion, Vec3};
+use crate::random::rand_f64;
+use crate::ray::Ray;
+use crate::sampling::{self, Distribution1D};
+
+// poses sampled along the motion to bound a moving instance
+const MOTION_STEPS: usize = 16;
+
+fn default_scale() -> (f64, f64, f64) {
+    (1.0, 1.0, 1.0)
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SerializationInstance {
+    // name of the [[Sphere]], [[Plane]] or [[Mesh]] to place
+    object: String,
+    #[serde(default)]
+    translation: (f64, f64, f64),
+    // Euler angles in degrees, applied around x, then y, then z
+    rotation: Option<(f64, f64, f64)>,
+    // (x, y, z, w), an alternative to `rotation`
+    quaternion: Option<(f64, f64, f64, f64)>,
+    #[serde(default = "default_scale")]
+    scale: (f64, f64, f64),
+    // pose at time 1 for an instance moving during the exposure, parts left out do not move
+    translation1: Option<(f64, f64, f64)>,
+    rotation1: Option<(f64, f64, f64)>,
+    quaternion1: Option<(f64, f64, f64, f64)>,
+    scale1: Option<(f64, f64, f64)>,
+}
+
+impl SerializationInstance {
+    pub fn object(&self) -> &str {
+        &self.object
+    }
+
+    fn rotation(
+        rotation: Option<(f64, f64, f64)>,
+        quaternion: Option<(f64, f64, f64, f64)>,
+    ) -> Option<Quaternion> {
+        match (rotation, quaternion) {
+            (Some((x, y, z)), _) => Some(Quaternion::from_euler(Vec3::new(
+                x.to_radians(),
+                y.to_radians(),
+                z.to_radians(),
+            ))),
+            (None, Some((x, y, z, w))) => Some(Quaternion::new(x, y, z, w)),
+            (None, None) => None,
+        }
+    }
+
+    fn start(&self) -> Transform {
+        Transform {
+            translation: self.translation.into(),
+            rotation: Self::rotation(self.rotation, self.quaternion)
+                .unwrap_or_else(Quaternion::identity),
+            scale: self.scale.into(),
+        }
+    }
+
+    fn end(&self) -> Transform {
+        let start = self.start();
+        Transform {
+            translation: self.translation1.map_or(start.translation, Vec3::from),
+            rotation: Self::rotation(self.rotation1, self.quaternion1).unwrap_or(start.rotation),
+            scale: self.scale1.map_or(start.scale, Vec3::from),
+        }
+    }
+}
+
+fn check_rotation(
+    field: &'static str,
+    rotation: Option<(f64, f64, f64)>,
+    quaternion: Option<(f64, f64, f64, f64)>,
+) -> Result<(), FieldError> {
+    if rotation.is_some() && quaternion.is_some() {
+        return Err(FieldError::new(
+            field,
+            "set either Euler angles or a quaternion, not both",
+        ));
+    }
+    if let Some((x, y, z, w)) = quaternion {
+        if !(x * x + y * y + z * z + w * w).is_normal() {
+            return Err(FieldError::new(field, "quaternion must not be zero"));
+        }
+    }
+    Ok(())
+}
+
+fn check_scale(field: &'static str, scale: (f64, f64, f64)) -> Result<(), FieldError> {
+    if [scale.0, scale.1, scale.2]
+        .iter()
+        .all(|s| s.is_finite() && *s != 0.0)
+    {
+        Ok(())
+    } else {
+        Err(FieldError::new(field, "scale must not be zero"))
+    }
+}
+
+impl Validate for SerializationInstance {
+    fn validate(&self) -> Result<(), FieldError> {
+        check_rotation("quaternion", self.rotation, self.quaternion)?;
+        check_rotation("quaternion1", self.rotation1, self.quaternion1)?;
+        check_scale("scale", self.scale)?;
+        if let Some(scale1) = self.scale1 {
+            check_scale("scale1", scale1)?;
+        }
+        Ok(())
+    }
+}
+
+// scale, then rotate, then translate
+#[derive(Clone, Copy, Debug)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Quaternion,
+    pub scale: Vec3,
+}
+
+impl Transform {
+    pub fn matrix(&self) -> Mat4 {
+        Mat4::translation(self.translation)
+            * Mat4::rotation(self.rotation)
+            * Mat4::scale(self.scale)
+    }
+
+    pub fn inverse(&self) -> Mat4 {
+        let s = self.scale;
+        Mat4::scale(Vec3::new(1.0 / s.x, 1.0 / s.y, 1.0 / s.z))
+            * Mat4::rotation(self.rotation.conjugate())
+            * Mat4::translation(self.translation * (-1.0))
+    }
+
+    pub fn lerp(&self, other: &Transform, t: f64) -> Transform {
+        Transform {
+            translation: self.translation + (other.translation - self.translation) * t,
+            rotation: self.rotation.slerp(&other.rotation, t),
+            scale: self.scale + (other.scale - self.scale) * t,
+        }
+    }
+}
+
+// places a shared object in the world, moving from `start` at time 0 to `end` at time 1
+pub struct Instance {
+    object: Arc<dyn Hittable>,
+    start: Transform,
+    end: Option<Transform>,
+    matrix: Mat4,
+    inverse: Mat4,
+    bbox: Aabb,
+    // the object's surfaces when it is emissive, picked by their area once placed
+    surfaces: Vec<Arc<dyn Surface>>,
+    areas: Distribution1D,
+    area: f64,
+}
+
+impl Instance {
+    pub fn new(object: Arc<dyn Hittable>, transform: Transform) -> Self {
+        Self::moving(object, transform, None)
+    }
+
+    pub fn moving(object: Arc<dyn Hittable>, start: Transform, end: Option<Transform>) -> Self {
+        let local = object.bounding_box();
+        let bbox = match end {
+            _ if local.is_empty() => local,
+            None => transformed_box(&local, &start.matrix()),
+            Some(end) => {
+                let mut bbox = Aabb::empty();
+                for step in 0..=MOTION_STEPS {
+                    let pose = start.lerp(&end, step as f64 / MOTION_STEPS as f64);
+                    bbox = bbox.union(&transformed_box(&local, &pose.matrix()));
+                }
+                // corners swing out on arcs between the sampled poses, pad by the bulge
+                let scale = Vec3::new(
+                    start.scale.x.abs().max(end.scale.x.abs()),
+                    start.scale.y.abs().max(end.scale.y.abs()),
+                    start.scale.z.abs().max(end.scale.z.abs()),
+                );
+                let radius = local
+                    .corners()
+                    .iter()
+                    .map(|c| Vec3::new(c.x * scale.x, c.y * scale.y, c.z * scale.z).length())
+                    .fold(0.0, f64::max);
+                let step_angle =
+                    (start.rotation.conjugate() * end.rotation).angle() / MOTION_STEPS as f64;
+                let pad = radius * step_angle / 2.0;
+                let pad = Vec3::new(pad, pad, pad);
+                Aabb::new(bbox.min - pad, bbox.max + pad)
+            }
+        };
+        Self {
+            object,
+            start,
+            end,
+            matrix: start.matrix(),
+            inverse: start.inverse(),
+            bbox,
+            surfaces: vec![],
+            areas: Distribution1D::new(vec![]),
+            area: 0.0,
+        }
+    }
+
+    // lets the instance be sampled as a light, the areas are measured in the starting pose
+    pub fn with_surfaces(mut self, surfaces: Vec<Arc<dyn Surface>>) -> Self {
+        let areas: Vec<f64> = surfaces
+            .iter()
+            .map(|surface| {
+                // how much the pose stretches the surface around one of its normals, exact for
+                // flat surfaces and for uniformly scaled spheres
+                let (_, normal) = surface.sample_point(0.0);
+                let a = self
+                    .matrix
+                    .transform_vector(sampling::local_to_world(Vec3::new(1.0, 0.0, 0.0), normal));
+                let b = self
+                    .matrix
+                    .transform_vector(sampling::local_to_world(Vec3::new(0.0, 1.0, 0.0), normal));
+                surface.area() * a.cross(&b).length()
+            })
+            .collect();
+        self.area = areas.iter().sum();
+        self.areas = Distribution1D::new(areas);
+        self.surfaces = surfaces;
+        self
+    }
+
+    // `with_surfaces` samples uniformly by area only while the pose stretches every surface
+    // evenly, which a non-uniform scale breaks for curved surfaces and a changing scale for all
+    pub fn samples_uniformly(&self, flat: bool) -> bool {
+        let scale = self.start.scale;
+        let steady = self.end.is_none_or(|end| end.scale == scale);
+        let uniform = scale.x.abs() == scale.y.abs() && scale.y.abs() == scale.z.abs();
+        steady && (flat || uniform)
+    }
+
+    pub fn from_serialization(value: &SerializationInstance, object: Arc<dyn Hittable>) -> Self {
+        let start = value.start();
+        let end = value.end();
+        let moving = end.translation != start.translation
+            || end.rotation != start.rotation
+            || end.scale != start.scale;
+        if moving {
+            Self::moving(object, start, Some(end))
+        } else {
+            Self::new(object, start)
+        }
+    }
+
+    // object to world space at `time` and its inverse
+    fn matrices(&self, time: f64) -> (Mat4, Mat4) {
+        match &self.end {
+            Some(end) => {
+                let pose = self.start.lerp(end, time);
+                (pose.matrix(), pose.inverse())
+            }
+            None => (self.matrix, self.inverse),
+        }
+    }
+}
+
+fn transformed_box(bbox: &Aabb, matrix: &Mat4) -> Aabb {
+    Aabb::from_points(&bbox.corners().map(|c| matrix.transform_point(c)))
+}
+
+impl Hittable for Instance {
+    fn get_hit_record(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let (matrix, inverse) = self.matrices(ray.time);
+        // the local direction is normalized, distances along it are `scale` times longer
+        let direction = inverse.transform_vector(ray.direction);
+        let scale = direction.length();
+        if scale == 0.0 {
+            return None;
+        }
+        let local_ray =
+            Ray::new(inverse.transform_point(ray.origin), direction).with_time(ray.time);
+
+        let mut record = self
+            .object
+            .get_hit_record(&local_ray, t_min * scale, t_max * scale)?;
+        record.t /= scale;
+        // emissive lights recognise the instance rather than the shared object
+        record.obj = self;
+        // `object` stays in the prototype's space
+        record.point = ray.at(record.t);
+        // normals transform with the inverse transpose to stay perpendicular under scaling
+        let normal_matrix = inverse.transpose();
+        record.normal = normal_matrix.transform_vector(record.normal).normalize();
+        record.geometric_normal = normal_matrix
+            .transform_vector(record.geometric_normal)
+            .normalize();
+        record.dpdu = matrix.transform_vector(record.dpdu);
+        record.dpdv = matrix.transform_vector(record.dpdv);
+        Some(record)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bbox
+    }
+}
+
+impl Surface for Instance {
+    fn area(&self) -> f64 {
+        self.area
+    }
+
+    fn sample_point(&self, time: f64) -> (Point3, Vec3) {
+        let (matrix, inverse) = self.matrices(time);
+        let (_, _, index) = self.areas.sample(rand_f64());
+        let (point, normal) = self.surfaces[index].sample_point(time);
+        (
+            matrix.transform_point(point),
+            inverse.transpose().transform_vector(normal).normalize(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f64::consts::PI;
+    use std::sync::Arc;
+
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::{Instance, Transform};
+    use crate::{
+        hit::{Hittable, Surface},
+        material::{Diffuse, Material},
+        maths::{Color, Quaternion, Vec3},
+        objects::{Plane, Sphere},
+        ray::Ray,
+    };
+
+    fn rand_vec(rng: &mut StdRng, range: f64) -> Vec3 {
+        Vec3::new(
+            rng.gen_range(-range..range),
+            rng.gen_range(-range..range),
+            rng.gen_range(-range..range),
+        )
+    }
+
+    #[test]
+    fn instanced_sphere_matches_direct_sphere() {
+        let material: Arc<dyn Material> = Arc::new(Diffuse::new(Color::new(0.5, 0.5, 0.5)));
+        let unit = Arc::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, material.clone()));
+        // uniform scale keeps it a sphere, the rotation must not show
+        let instance = Instance::new(
+            unit,
+            Transform {
+                translation: Vec3::new(1.0, -2.0, 3.0),
+                rotation: Quaternion::from_axis_angle(Vec3::new(1.0, 1.0, 0.0), 0.7),
+                scale: Vec3::new(2.5, 2.5, 2.5),
+            },
+        );
+        let direct = Sphere::new(Vec3::new(1.0, -2.0, 3.0), 2.5, material);
+
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let ray = Ray::new(rand_vec(&mut rng, 8.0), rand_vec(&mut rng, 1.0));
+            let a = instance.get_hit_record(&ray, 0.0001, f64::INFINITY);
+            let b = direct.get_hit_record(&ray, 0.0001, f64::INFINITY);
+            match (a, b) {
+                (Some(a), Some(b)) => {
+                    assert!((a.t - b.t).abs() < 1e-9);
+                    assert!((a.point - b.point).length() < 1e-9);
+                    assert!((a.normal - b.normal).length() < 1e-9);
+                    // textures see the point on the unit sphere, not where it was placed
+                    assert!((a.object.point.length() - 1.0).abs() < 1e-9);
+                    assert!((b.object.point.length() - 2.5).abs() < 1e-9);
+                    assert!(instance.bounding_box().hit(&ray, 0.0001, f64::INFINITY));
+                    hits += 1;
+                }
+                (None, None) => {}
+                _ => panic!("instance and sphere disagree"),
+            }
+        }
+        assert!(hits > 0);
+    }
+
+    #[test]
+    fn moving_instance_stays_inside_its_bounds() {
+        let material: Arc<dyn Material> = Arc::new(Diffuse::new(Color::new(0.5, 0.5, 0.5)));
+        let sphere = Arc::new(Sphere::new(Vec3::new(2.0, 0.0, 0.0), 0.5, material));
+        let start = Transform {
+            translation: Vec3::new(0.0, 0.0, 0.0),
+            rotation: Quaternion::identity(),
+            scale: Vec3::new(1.0, 2.0, 1.0),
+        };
+        let end = Transform {
+            translation: Vec3::new(0.0, 1.0, 0.0),
+            rotation: Quaternion::from_axis_angle(Vec3::new(0.0, 0.0, 1.0), 3.0),
+            scale: Vec3::new(1.0, 1.0, 3.0),
+        };
+        let instance = Instance::moving(sphere, start, Some(end));
+        let bbox = instance.bounding_box();
+
+        let mut rng = StdRng::seed_from_u64(5);
+        for _ in 0..2000 {
+            let ray = Ray::new(rand_vec(&mut rng, 6.0), rand_vec(&mut rng, 1.0))
+                .with_time(rng.gen_range(0.0..1.0));
+            if let Some(record) = instance.get_hit_record(&ray, 0.0001, f64::INFINITY) {
+                let p = record.point;
+                assert!((0..3).all(
+                    |axis| p[axis] >= bbox.min[axis] - 1e-9 && p[axis] <= bbox.max[axis] + 1e-9
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn instanced_surfaces_sample_the_placed_object() {
+        let material: Arc<dyn Material> = Arc::new(Diffuse::new(Color::new(0.5, 0.5, 0.5)));
+        // a unit square facing +z, stretched and turned to face +x
+        let square = Arc::new(Plane::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            material,
+        ));
+        let instance = Instance::new(
+            square.clone(),
+            Transform {
+                translation: Vec3::new(0.0, 1.0, 0.0),
+                rotation: Quaternion::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), 0.5 * PI),
+                scale: Vec3::new(2.0, 3.0, 1.0),
+            },
+        )
+        .with_surfaces(vec![square]);
+        assert!((instance.area() - 6.0).abs() < 1e-9);
+
+        for _ in 0..100 {
+            let (point, normal) = instance.sample_point(0.0);
+            assert!((normal - Vec3::new(1.0, 0.0, 0.0)).length() < 1e-9);
+            // the sample lies on the instance, where a ray along the normal finds it
+            let ray = Ray::new(point + normal, normal * (-1.0));
+            let record = instance
+                .get_hit_record(&ray, 0.0001, f64::INFINITY)
+                .unwrap();
+            assert!((record.point - point).length() < 1e-9);
+            assert!(std::ptr::addr_eq(record.obj, &instance));
+        }
+    }
+
+    #[test]
+    fn only_evenly_stretched_instances_sample_uniformly() {
+        let material: Arc<dyn Material> = Arc::new(Diffuse::new(Color::new(0.5, 0.5, 0.5)));
+        let sphere = Arc::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, material));
+        let pose = |scale| Transform {
+            translation: Vec3::new(1.0, 2.0, 3.0),
+            rotation: Quaternion::from_axis_angle(Vec3::new(1.0, 0.0, 0.0), 0.4),
+            scale,
+        };
+        let even = pose(Vec3::new(2.0, -2.0, 2.0));
+        let uneven = pose(Vec3::new(2.0, 1.0, 2.0));
+
+        assert!(Instance::new(sphere.clone(), even).samples_uniformly(false));
+        assert!(!Instance::new(sphere.clone(), uneven).samples_uniformly(false));
+        assert!(Instance::new(sphere.clone(), uneven).samples_uniformly(true));
+        // moving keeps the stretch, growing does not
+        let moved = Transform {
+            translation: Vec3::new(0.0, 0.0, 0.0),
+            ..uneven
+        };
+        assert!(Instance::moving(sphere.clone(), uneven, Some(moved)).samples_uniformly(true));
+        let grown = pose(Vec3::new(3.0, 3.0, 3.0));
+        assert!(!Instance::moving(sphere, even, Some(grown)).samples_uniformly(true));
+    }
+}
@@ -0,0 +1,268 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::aabb::Aabb;
+use crate::bvh::BvhNode;
+use crate::error::{FieldError, RaytError, Validate};
+use crate::hit::{HitRecord, Hittable};
+use crate::material::Material;
+use crate::maths::{Point3, Vec3};
+use crate::ray::Ray;
+
+use super::Triangle;
+
+use serde::Deserialize;
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SerializationMesh {
+    // named meshes are not placed themselves, [[Instance]] entries place copies of them
+    name: Option<String>,
+    path: PathBuf,
+    #[serde(default)]
+    position: (f64, f64, f64),
+    #[serde(default = "default_scale")]
+    scale: f64,
+    material: String,
+}
+
+impl SerializationMesh {
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn material(&self) -> &str {
+        &self.material
+    }
+
+    // OBJ paths are written relative to the scene file
+    pub fn resolve_path(&mut self, scene_dir: &Path) {
+        self.path = scene_dir.join(&self.path);
+    }
+}
+
+impl Validate for SerializationMesh {
+    fn validate(&self) -> Result<(), FieldError> {
+        if !(self.scale.is_finite() && self.scale != 0.0) {
+            return Err(FieldError::new("scale", "scale must not be zero"));
+        }
+        Ok(())
+    }
+}
+
+pub struct Mesh {
+    triangles: Vec<Arc<Triangle>>,
+    bvh: BvhNode,
+}
+
+impl Mesh {
+    pub fn new(triangles: Vec<Triangle>) -> Self {
+        let triangles: Vec<Arc<Triangle>> = triangles.into_iter().map(Arc::new).collect();
+        let objects: Vec<Arc<dyn Hittable>> = triangles
+            .iter()
+            .map(|triangle| triangle.clone() as Arc<dyn Hittable>)
+            .collect();
+        Self {
+            bvh: BvhNode::new(&objects),
+            triangles,
+        }
+    }
+
+    pub fn triangles(&self) -> &[Arc<Triangle>] {
+        &self.triangles
+    }
+
+    pub fn from_serialization(
+        value: &SerializationMesh,
+        material: Arc<dyn Material>,
+    ) -> Result<Self, RaytError> {
+        let obj = fs::read_to_string(&value.path)
+            .and_then(|source| ObjData::parse(&source))
+            .map_err(|source| RaytError::Io {
+                path: value.path.clone(),
+                source,
+            })?;
+        let position: Vec3 = value.position.into();
+        let triangles = obj
+            .faces
+            .iter()
+            .map(|face| {
+                let normals = face
+                    .iter()
+                    .map(|v| v.normal.map(|i| obj.normals[i]))
+                    .collect::<Option<Vec<_>>>();
+                let uvs = face
+                    .iter()
+                    .map(|v| v.uv.map(|i| obj.uvs[i]))
+                    .collect::<Option<Vec<_>>>();
+                Triangle::new(
+                    face.map(|v| obj.positions[v.position] * value.scale + position),
+                    normals.map(|n| [n[0], n[1], n[2]]),
+                    uvs.map(|uv| [uv[0], uv[1], uv[2]]),
+                    material.clone(),
+                )
+                .with_placement(position, value.scale)
+            })
+            .collect();
+        Ok(Mesh::new(triangles))
+    }
+}
+
+impl Hittable for Mesh {
+    fn get_hit_record(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.bvh.get_hit_record(ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        self.bvh.bounding_box()
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct ObjVertex {
+    position: usize,
+    uv: Option<usize>,
+    normal: Option<usize>,
+}
+
+// the subset of Wavefront OBJ we need: positions, texture coordinates, normals and
+// polygonal faces, which are split into triangle fans
+#[derive(Default, Debug)]
+struct ObjData {
+    positions: Vec<Point3>,
+    uvs: Vec<(f64, f64)>,
+    normals: Vec<Vec3>,
+    faces: Vec<[ObjVertex; 3]>,
+}
+
+impl ObjData {
+    fn parse(source: &str) -> io::Result<Self> {
+        let mut obj = Self::default();
+
+        for (line_number, line) in source.lines().enumerate() {
+            let error = |msg: &str| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: {}", line_number + 1, msg),
+                )
+            };
+            let mut tokens = line.split_whitespace();
+            let Some(keyword) = tokens.next() else {
+                continue;
+            };
+            let numbers = |tokens: std::str::SplitWhitespace, count: usize| {
+                let values = tokens
+                    .map(|t| t.parse::<f64>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|_| error("invalid number"))?;
+                if values.len() < count {
+                    return Err(error("not enough components"));
+                }
+                Ok(values)
+            };
+
+            match keyword {
+                "v" => {
+                    let v = numbers(tokens, 3)?;
+                    obj.positions.push(Vec3::new(v[0], v[1], v[2]));
+                }
+                "vt" => {
+                    let v = numbers(tokens, 1)?;
+                    obj.uvs.push((v[0], v.get(1).copied().unwrap_or(0.0)));
+                }
+                "vn" => {
+                    let v = numbers(tokens, 3)?;
+                    obj.normals.push(Vec3::new(v[0], v[1], v[2]).normalize());
+                }
+                "f" => {
+                    let vertices = tokens
+                        .map(|t| {
+                            obj.parse_vertex(t)
+                                .ok_or_else(|| error("invalid face index"))
+                        })
+                        .collect::<io::Result<Vec<_>>>()?;
+                    if vertices.len() < 3 {
+                        return Err(error("face needs at least three vertices"));
+                    }
+                    for i in 1..vertices.len() - 1 {
+                        obj.faces.push([vertices[0], vertices[i], vertices[i + 1]]);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(obj)
+    }
+
+    // `v`, `v/vt`, `v//vn` or `v/vt/vn`, 1-based or negative (relative to the end)
+    fn parse_vertex(&self, token: &str) -> Option<ObjVertex> {
+        let resolve = |index: &str, len: usize| -> Option<usize> {
+            let index: i64 = index.parse().ok()?;
+            let resolved = if index > 0 {
+                index - 1
+            } else {
+                len as i64 + index
+            };
+            (index != 0 && (0..len as i64).contains(&resolved)).then_some(resolved as usize)
+        };
+
+        let mut parts = token.split('/');
+        let position = resolve(parts.next()?, self.positions.len())?;
+        let uv = match parts.next() {
+            None | Some("") => None,
+            Some(index) => Some(resolve(index, self.uvs.len())?),
+        };
+        let normal = match parts.next() {
+            None | Some("") => None,
+            Some(index) => Some(resolve(index, self.normals.len())?),
+        };
+
+        Some(ObjVertex {
+            position,
+            uv,
+            normal,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ObjData;
+
+    #[test]
+    fn parse_obj_quad() {
+        let obj = ObjData::parse(
+            "# a unit quad
+            v 0 0 0
+            v 1 0 0
+            v 1 1 0
+            v 0 1 0
+            vt 0 0
+            vt 1 0
+            vt 1 1
+            vt 0 1
+            vn 0 0 2
+            f 1/1/1 2/2/1 3/3/1 -1/-1/-1",
+        )
+        .unwrap();
+
+        assert_eq!(obj.positions.len(), 4);
+        assert_eq!(obj.faces.len(), 2);
+        assert_eq!(obj.faces[1][2].position, 3);
+        assert_eq!(obj.faces[1][2].uv, Some(3));
+        assert_eq!(obj.uvs[2], (1.0, 1.0));
+        assert_eq!(obj.normals[0].z, 1.0);
+    }
+
+    #[test]
+    fn parse_obj_rejects_bad_index() {
+        assert!(ObjData::parse("v 0 0 0\nf 1 2 3").is_err());
+    }
+}
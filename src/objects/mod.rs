@@ -1,5 +1,152 @@
+pub mod instance;
+pub mod mesh;
 pub mod plane;
 pub mod sphere;
+pub mod triangle;
 
+pub use instance::Instance;
+pub use mesh::Mesh;
 pub use plane::Plane;
 pub use sphere::Sphere;
+pub use triangle::Triangle;
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{Plane, Sphere, Triangle};
+    use crate::{
+        hit::Hittable,
+        material::{Diffuse, Material},
+        maths::{Color, Vec3},
+        ray::Ray,
+    };
+
+    // stepping along dp/du or dp/dv from a hit has to move the uv by the same amount
+    fn check_tangents(object: &dyn Hittable, origin: Vec3, target: Vec3) {
+        let ray = Ray::new(origin, target - origin);
+        let record = object.get_hit_record(&ray, 1e-9, f64::INFINITY).unwrap();
+        assert!((record.dpdu * record.geometric_normal).abs() < 1e-9);
+        assert!((record.dpdv * record.geometric_normal).abs() < 1e-9);
+
+        let step = 1e-5;
+        for (tangent, expected) in [(record.dpdu, (1.0, 0.0)), (record.dpdv, (0.0, 1.0))] {
+            let moved = record.point + tangent * step;
+            let n = record.geometric_normal;
+            let probe = Ray::new(moved + n * 0.01, n * (-1.0));
+            let other = object.get_hit_record(&probe, 1e-9, f64::INFINITY).unwrap();
+            let du = (other.uv.0 - record.uv.0) / step;
+            let dv = (other.uv.1 - record.uv.1) / step;
+            assert!((du - expected.0).abs() < 1e-3, "du {} dv {}", du, dv);
+            assert!((dv - expected.1).abs() < 1e-3, "du {} dv {}", du, dv);
+        }
+    }
+
+    #[test]
+    fn tangents_follow_the_uv_mapping() {
+        let material: Arc<dyn Material> = Arc::new(Diffuse::new(Color::new(0.5, 0.5, 0.5)));
+        let sphere = Sphere::new(Vec3::new(1.0, 0.5, -3.0), 1.5, material.clone());
+        check_tangents(&sphere, Vec3::new(0.0, 0.0, 0.0), Vec3::new(1.5, 0.9, -3.0));
+        check_tangents(
+            &sphere,
+            Vec3::new(4.0, -2.0, -1.0),
+            Vec3::new(1.0, 0.5, -3.0),
+        );
+
+        let plane = Plane::new(
+            Vec3::new(-1.0, -1.0, -2.0),
+            Vec3::new(2.0, 0.0, 0.5),
+            Vec3::new(0.0, 3.0, 0.0),
+            material.clone(),
+        );
+        check_tangents(&plane, Vec3::new(0.0, 0.0, 0.0), Vec3::new(0.2, 0.4, -1.9));
+
+        let vertices = [
+            Vec3::new(-1.0, -1.0, -2.0),
+            Vec3::new(1.0, -1.0, -2.5),
+            Vec3::new(0.0, 1.0, -2.0),
+        ];
+        let uvs = [(0.1, 0.2), (0.9, 0.1), (0.4, 0.8)];
+        let origin = Vec3::new(0.1, 0.2, 1.0);
+        let target = Vec3::new(0.0, -0.3, -2.2);
+        check_tangents(
+            &Triangle::new(vertices, None, Some(uvs), material.clone()),
+            origin,
+            target,
+        );
+        check_tangents(
+            &Triangle::new(vertices, None, None, material),
+            origin,
+            target,
+        );
+    }
+
+    #[test]
+    fn moving_sphere_is_hit_where_it_is_at_the_ray_time() {
+        let material: Arc<dyn Material> = Arc::new(Diffuse::new(Color::new(0.5, 0.5, 0.5)));
+        let start = Vec3::new(0.0, 0.0, -3.0);
+        let end = Vec3::new(2.0, 1.0, -3.0);
+        let sphere = Sphere::moving(start, end, 0.5, material);
+
+        for time in [0.0, 0.25, 0.5, 1.0] {
+            let center = start + (end - start) * time;
+            let ray = Ray::new(center + Vec3::new(0.0, 0.0, 5.0), Vec3::new(0.0, 0.0, -1.0))
+                .with_time(time);
+            let record = sphere.get_hit_record(&ray, 1e-9, f64::INFINITY).unwrap();
+            assert!((record.point - (center + Vec3::new(0.0, 0.0, 0.5))).length() < 1e-9);
+            assert!((record.normal - Vec3::new(0.0, 0.0, 1.0)).length() < 1e-9);
+        }
+        // where the sphere starts it is gone by the end of the exposure
+        let ray = Ray::new(Vec3::new(0.0, 0.0, 2.0), Vec3::new(0.0, 0.0, -1.0)).with_time(1.0);
+        assert!(sphere.get_hit_record(&ray, 1e-9, f64::INFINITY).is_none());
+
+        // the box holds the sphere at both ends of its motion
+        let bbox = sphere.bounding_box();
+        let r = Vec3::new(0.5, 0.5, 0.5);
+        for center in [start, end] {
+            let (low, high) = (center - r, center + r);
+            assert!(bbox.min.x <= low.x && bbox.min.y <= low.y && bbox.min.z <= low.z);
+            assert!(bbox.max.x >= high.x && bbox.max.y >= high.y && bbox.max.z >= high.z);
+        }
+    }
+
+    // procedural textures have to stay attached to a primitive wherever it is placed
+    #[test]
+    fn object_space_follows_placement() {
+        let material: Arc<dyn Material> = Arc::new(Diffuse::new(Color::new(0.5, 0.5, 0.5)));
+        let offset = Vec3::new(3.0, -1.0, 2.0);
+        let object_point = |object: &dyn Hittable, target: Vec3| {
+            let origin = target + Vec3::new(0.2, 0.3, 4.0);
+            let ray = Ray::new(origin, target - origin);
+            object
+                .get_hit_record(&ray, 1e-9, f64::INFINITY)
+                .unwrap()
+                .object
+                .point
+        };
+
+        let edges = (Vec3::new(2.0, 0.0, 0.0), Vec3::new(0.0, 2.0, 0.0));
+        let corner = Vec3::new(-1.0, -1.0, -2.0);
+        let plane = Plane::new(corner, edges.0, edges.1, material.clone());
+        let moved = Plane::new(corner + offset, edges.0, edges.1, material.clone());
+        let target = Vec3::new(0.3, 0.4, -2.0);
+        let a = object_point(&plane, target);
+        let b = object_point(&moved, target + offset);
+        assert!((a - b).length() < 1e-9);
+
+        // a mesh at `offset` scaled by 2 sees the coordinates of its file
+        let vertices = [
+            Vec3::new(-1.0, -1.0, -2.0),
+            Vec3::new(1.0, -1.0, -2.0),
+            Vec3::new(0.0, 1.0, -2.0),
+        ];
+        let triangle = Triangle::new(vertices, None, None, material.clone());
+        let placed = Triangle::new(vertices.map(|v| v * 2.0 + offset), None, None, material)
+            .with_placement(offset, 2.0);
+        let target = Vec3::new(0.1, -0.2, -2.0);
+        let a = object_point(&triangle, target);
+        let b = object_point(&placed, target * 2.0 + offset);
+        assert!((a - b).length() < 1e-9);
+        assert!((a - target).length() < 1e-9);
+    }
+}
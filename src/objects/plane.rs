@@ -1,5 +1,11 @@
-use crate::hit::{Front, HitRecord, Hittable};
-use crate::maths::{Color, Point3, Vec3};
+use crate::aabb::Aabb;
+use std::sync::Arc;
+
+use crate::error::{FieldError, Validate};
+use crate::hit::{Front, HitRecord, Hittable, ObjectSpace, Surface};
+use crate::material::Material;
+use crate::maths::{Point3, Vec3};
+use crate::random::rand_f64;
 use crate::ray::Ray;
 
 use serde::Deserialize;
@@ -9,51 +15,61 @@ pub struct SerializationPlane {
     origin: (f64, f64, f64),
     edge_x: (f64, f64, f64),
     edge_y: (f64, f64, f64),
-    color: (f64, f64, f64),
-    roughness: f64,
-    reflectivity: f64,
+    material: String,
+    // named objects are not placed themselves, [[Instance]] entries place copies of them
+    name: Option<String>,
 }
+
+impl SerializationPlane {
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn material(&self) -> &str {
+        &self.material
+    }
+}
+
 pub struct Plane {
     origin: Point3,
     edge_x: Vec3,
     edge_y: Vec3,
-    color: Color,
-    roughness: f64,
-    reflectivity: f64,
+    material: Arc<dyn Material>,
 }
 
-impl From<&SerializationPlane> for Plane {
-    fn from(value: &SerializationPlane) -> Self {
-        Self {
-            origin: value.origin.into(),
-            edge_x: value.edge_x.into(),
-            edge_y: value.edge_y.into(),
-            color: value.color.into(),
-            roughness: value.roughness,
-            reflectivity: value.reflectivity,
+impl Validate for SerializationPlane {
+    fn validate(&self) -> Result<(), FieldError> {
+        let edge_x: Vec3 = self.edge_x.into();
+        let edge_y: Vec3 = self.edge_y.into();
+        if edge_x.cross(&edge_y).length() == 0.0 {
+            return Err(FieldError::new(
+                "edge_y",
+                "edges must not be zero or parallel to each other",
+            ));
         }
+        Ok(())
     }
 }
 
 impl Plane {
-    pub fn new(
-        origin: Vec3,
-        edge_x: Vec3,
-        edge_y: Vec3,
-        color: Color,
-        roughness: f64,
-        reflectivity: f64,
-    ) -> Self {
+    pub fn new(origin: Vec3, edge_x: Vec3, edge_y: Vec3, material: Arc<dyn Material>) -> Self {
         Self {
             origin,
             edge_x,
             edge_y,
-            color,
-            roughness,
-            reflectivity,
+            material,
         }
     }
 
+    pub fn from_serialization(value: &SerializationPlane, material: Arc<dyn Material>) -> Self {
+        Self::new(
+            value.origin.into(),
+            value.edge_x.into(),
+            value.edge_y.into(),
+            material,
+        )
+    }
+
     pub fn vertices(&self) -> [Vec3; 4] {
         [
             self.origin,
@@ -65,7 +81,7 @@ impl Plane {
 }
 
 impl Hittable for Plane {
-    fn get_hit_record(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn get_hit_record(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         // note: Figure <X> means that X is a vector.
 
         // == PROOF ==
@@ -84,14 +100,14 @@ impl Hittable for Plane {
         //             Ax + By + Cz + D = 0
 
         // use the plane equation ax + by + cz + d = 0, where (a, b, c) is the normal vector of the plane
-        let mut normal = self.edge_x.cross(&self.edge_y).normalize();
-        
+        let normal = self.edge_x.cross(&self.edge_y).normalize();
+
         if ray.direction * normal == 0.0 {
             return None;
         }
 
         // solve for d
-        let d = normal * self.origin * (-1.0);
+        let d = -(normal * self.origin);
 
         // calculate k in line parametric equation <P> = <O> + t * <D>
 
@@ -145,29 +161,46 @@ impl Hittable for Plane {
             }
         }
 
-        // flip the normal vector if necessary
-        if ray.direction * normal > 0.0 {
-            normal = normal * (-1.0);
-        }
+        // the m and n of Plane.md, coordinates of the point along the two edges
+        let offset = intersection - self.origin;
+        let area = self.edge_x.cross(&self.edge_y) * normal;
+        let u = offset.cross(&self.edge_y) * normal / area;
+        let v = self.edge_x.cross(&offset) * normal / area;
+
+        // the winding of the edges decides which side is the outside
+        let (front_face, normal) = Front::from_outward_normal(ray, normal);
 
-        return Some(HitRecord {
+        Some(HitRecord {
             obj: self,
+            material: self.material.as_ref(),
             point: intersection,
             normal,
-            front_face: Front::Outward,
+            geometric_normal: normal,
+            uv: (u, v),
+            dpdu: self.edge_x,
+            dpdv: self.edge_y,
+            object: ObjectSpace {
+                point: intersection - self.origin,
+                dpdu: self.edge_x,
+                dpdv: self.edge_y,
+            },
+            front_face,
             t,
-        });
+        })
     }
 
-    fn get_color(&self) -> Color {
-        self.color
+    fn bounding_box(&self) -> Aabb {
+        Aabb::from_points(&self.vertices())
     }
+}
 
-    fn get_roughness(&self) -> f64 {
-        self.roughness
+impl Surface for Plane {
+    fn area(&self) -> f64 {
+        self.edge_x.cross(&self.edge_y).length()
     }
 
-    fn get_reflectivity(&self) -> f64 {
-        self.reflectivity
+    fn sample_point(&self, _time: f64) -> (Point3, Vec3) {
+        let point = self.origin + self.edge_x * rand_f64() + self.edge_y * rand_f64();
+        (point, self.edge_x.cross(&self.edge_y).normalize())
     }
 }
@@ -1,6 +1,13 @@
-use crate::hit::{Front, HitRecord, Hittable};
-use crate::maths::{Color, Point3, Vec3};
+use crate::aabb::Aabb;
+use core::f64::consts::PI;
+use std::sync::Arc;
+
+use crate::error::{FieldError, Validate};
+use crate::hit::{Front, HitRecord, Hittable, ObjectSpace, Surface};
+use crate::material::Material;
+use crate::maths::{Point3, Vec3};
 use crate::ray::Ray;
+use crate::sampling;
 
 use serde::Deserialize;
 
@@ -8,45 +15,72 @@ use serde::Deserialize;
 pub struct SerializationSphere {
     radius: f64,
     center: (f64, f64, f64),
-    color: (f64, f64, f64),
-    roughness: f64,
-    reflectivity: f64,
+    // center at time 1 for a sphere moving during the exposure
+    center1: Option<(f64, f64, f64)>,
+    material: String,
+    // named objects are not placed themselves, [[Instance]] entries place copies of them
+    name: Option<String>,
+}
+
+impl SerializationSphere {
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn material(&self) -> &str {
+        &self.material
+    }
 }
+
 pub struct Sphere {
     radius: f64,
+    // the center moves linearly from `center` at time 0 to `center1` at time 1
     center: Point3,
-    color: Color,
-    roughness: f64,
-    reflectivity: f64,
+    center1: Point3,
+    material: Arc<dyn Material>,
 }
 
-impl From<&SerializationSphere> for Sphere {
-    fn from(value: &SerializationSphere) -> Self {
-        Self {
-            radius: value.radius,
-            center: value.center.into(),
-            color: value.color.into(),
-            roughness: value.roughness,
-            reflectivity: value.reflectivity,
+impl Validate for SerializationSphere {
+    fn validate(&self) -> Result<(), FieldError> {
+        if !(self.radius.is_finite() && self.radius > 0.0) {
+            return Err(FieldError::new("radius", "radius must be positive"));
         }
+        Ok(())
     }
 }
 
 impl Sphere {
-    pub fn new(center: Vec3, radius: f64, color: Color, roughness: f64, reflectivity: f64) -> Self {
+    pub fn new(center: Vec3, radius: f64, material: Arc<dyn Material>) -> Self {
+        Self::moving(center, center, radius, material)
+    }
+
+    pub fn moving(center0: Vec3, center1: Vec3, radius: f64, material: Arc<dyn Material>) -> Self {
         Self {
             radius,
-            center,
-            color,
-            roughness,
-            reflectivity,
+            center: center0,
+            center1,
+            material,
         }
     }
+
+    pub fn from_serialization(value: &SerializationSphere, material: Arc<dyn Material>) -> Self {
+        match value.center1 {
+            Some(center1) => {
+                Self::moving(value.center.into(), center1.into(), value.radius, material)
+            }
+            None => Self::new(value.center.into(), value.radius, material),
+        }
+    }
+
+    fn center_at(&self, time: f64) -> Point3 {
+        self.center + (self.center1 - self.center) * time
+    }
 }
 
 impl Hittable for Sphere {
-    fn get_hit_record(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let oc = ray.origin - self.center;
+    fn get_hit_record(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let center = self.center_at(ray.time);
+        let oc = ray.origin - center;
         let a = ray.direction.length_squared();
         let half_b = oc * ray.direction;
         let c = oc.length_squared() - self.radius * self.radius;
@@ -64,32 +98,54 @@ impl Hittable for Sphere {
             }
         }
 
-        let outward_normal = (ray.at(root) - self.center) / self.radius;
+        let outward_normal = (ray.at(root) - center) / self.radius;
+        let (front_face, normal) = Front::from_outward_normal(ray, outward_normal);
+
+        // u goes around the y axis starting at -x, v from the bottom pole to the top one
+        let n = outward_normal;
+        let phi = (-n.z).atan2(n.x) + PI;
+        let theta = (-n.y).clamp(-1.0, 1.0).acos();
+        // derivatives of r (-sin(theta) cos(phi), -cos(theta), sin(theta) sin(phi)), the
+        // poles are nudged off the axis where u stops mattering
+        let sin_theta = theta.sin().max(1e-9);
+        let dpdu = Vec3::new(n.z, 0.0, -n.x) * (2.0 * PI * self.radius);
+        let dpdv = Vec3::new(-n.x * n.y / sin_theta, sin_theta, -n.y * n.z / sin_theta)
+            * (PI * self.radius);
 
         Some(HitRecord {
             obj: self,
+            material: self.material.as_ref(),
             point: ray.at(root),
-            normal: outward_normal,
-            front_face: {
-                if ray.direction * outward_normal < 0.0 {
-                    Front::Inward
-                } else {
-                    Front::Outward
-                }
+            normal,
+            geometric_normal: normal,
+            uv: (phi / (2.0 * PI), theta / PI),
+            dpdu,
+            dpdv,
+            object: ObjectSpace {
+                point: ray.at(root) - center,
+                dpdu,
+                dpdv,
             },
+            front_face,
             t: root,
         })
     }
-    
-    fn get_color(&self) -> Color {
-        self.color
+
+    fn bounding_box(&self) -> Aabb {
+        // covers the sphere over the whole motion
+        let r = Vec3::new(self.radius, self.radius, self.radius);
+        Aabb::new(self.center - r, self.center + r)
+            .union(&Aabb::new(self.center1 - r, self.center1 + r))
     }
+}
 
-    fn get_roughness(&self) -> f64 {
-        self.roughness
+impl Surface for Sphere {
+    fn area(&self) -> f64 {
+        4.0 * PI * self.radius * self.radius
     }
 
-    fn get_reflectivity(&self) -> f64 {
-        self.reflectivity
+    fn sample_point(&self, time: f64) -> (Point3, Vec3) {
+        let (normal, _) = sampling::uniform_sphere();
+        (self.center_at(time) + normal * self.radius, normal)
     }
 }
@@ -0,0 +1,143 @@
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::hit::{Front, HitRecord, Hittable, ObjectSpace, Surface};
+use crate::material::Material;
+use crate::maths::{Point3, Vec3};
+use crate::ray::Ray;
+use crate::sampling;
+
+pub struct Triangle {
+    vertices: [Point3; 3],
+    normals: Option<[Vec3; 3]>,
+    uvs: Option<[(f64, f64); 3]>,
+    material: Arc<dyn Material>,
+    // position and scale of the mesh, procedural textures see the coordinates of its file
+    placement: (Vec3, f64),
+}
+
+impl Triangle {
+    pub fn new(
+        vertices: [Point3; 3],
+        normals: Option<[Vec3; 3]>,
+        uvs: Option<[(f64, f64); 3]>,
+        material: Arc<dyn Material>,
+    ) -> Self {
+        Self {
+            vertices,
+            normals,
+            uvs,
+            material,
+            placement: (Vec3::new(0.0, 0.0, 0.0), 1.0),
+        }
+    }
+
+    pub fn with_placement(mut self, position: Vec3, scale: f64) -> Self {
+        self.placement = (position, scale);
+        self
+    }
+}
+
+impl Hittable for Triangle {
+    fn get_hit_record(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        // Möller–Trumbore: solve O + tD = (1-u-v)V0 + uV1 + vV2 with Cramer's rule
+        let [v0, v1, v2] = self.vertices;
+        let e1 = v1 - v0;
+        let e2 = v2 - v0;
+
+        let p = ray.direction.cross(&e2);
+        let det = e1 * p;
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+
+        let s = ray.origin - v0;
+        let u = (s * p) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+
+        let q = s.cross(&e1);
+        let v = (ray.direction * q) * inv_det;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+
+        let t = (e2 * q) * inv_det;
+        if t <= t_min || t >= t_max {
+            return None;
+        }
+
+        let (front_face, geometric_normal) =
+            Front::from_outward_normal(ray, e1.cross(&e2).normalize());
+
+        let mut normal = match self.normals {
+            Some([n0, n1, n2]) => (n0 * (1.0 - u - v) + n1 * u + n2 * v).normalize(),
+            None => geometric_normal,
+        };
+
+        // without texture coordinates the barycentric ones stand in
+        let (uv, dpdu, dpdv) = match self.uvs {
+            Some([uv0, uv1, uv2]) => {
+                let uv = (
+                    uv0.0 * (1.0 - u - v) + uv1.0 * u + uv2.0 * v,
+                    uv0.1 * (1.0 - u - v) + uv1.1 * u + uv2.1 * v,
+                );
+                // solve e1 = du1 dpdu + dv1 dpdv and e2 = du2 dpdu + dv2 dpdv
+                let (du1, dv1) = (uv1.0 - uv0.0, uv1.1 - uv0.1);
+                let (du2, dv2) = (uv2.0 - uv0.0, uv2.1 - uv0.1);
+                let det = du1 * dv2 - dv1 * du2;
+                if det.abs() < 1e-12 {
+                    (uv, e1, e2)
+                } else {
+                    let dpdu = (e1 * dv2 - e2 * dv1) / det;
+                    let dpdv = (e2 * du1 - e1 * du2) / det;
+                    (uv, dpdu, dpdv)
+                }
+            }
+            None => ((u, v), e1, e2),
+        };
+
+        // vertex normals can disagree with the winding, face the interpolated one towards the ray
+        if ray.direction * normal > 0.0 {
+            normal = normal * (-1.0);
+        }
+
+        Some(HitRecord {
+            obj: self,
+            material: self.material.as_ref(),
+            point: ray.at(t),
+            normal,
+            geometric_normal,
+            uv,
+            dpdu,
+            dpdv,
+            object: ObjectSpace {
+                point: (ray.at(t) - self.placement.0) / self.placement.1,
+                dpdu: dpdu / self.placement.1,
+                dpdv: dpdv / self.placement.1,
+            },
+            front_face,
+            t,
+        })
+    }
+
+    fn bounding_box(&self) -> Aabb {
+        Aabb::from_points(&self.vertices)
+    }
+}
+
+impl Surface for Triangle {
+    fn area(&self) -> f64 {
+        let [v0, v1, v2] = self.vertices;
+        (v1 - v0).cross(&(v2 - v0)).length() / 2.0
+    }
+
+    // the winding decides the outward side, as for hits
+    fn sample_point(&self, _time: f64) -> (Point3, Vec3) {
+        let [v0, v1, v2] = self.vertices;
+        let (point, _) = sampling::uniform_triangle(self.vertices);
+        (point, (v1 - v0).cross(&(v2 - v0)).normalize())
+    }
+}
@@ -0,0 +1,87 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use image::{codecs::hdr::HdrEncoder, Rgb, Rgb32FImage, RgbImage};
+
+use crate::{error::RaytError, maths::Color, tonemap::DisplayTransform};
+
+pub fn to_ldr(img: &Rgb32FImage, display: &DisplayTransform) -> RgbImage {
+    RgbImage::from_fn(img.width(), img.height(), |x, y| {
+        let [r, g, b] = img.get_pixel(x, y).0;
+        Rgb::from(display.apply(Color::new(r as f64, g as f64, b as f64)))
+    })
+}
+
+// the format is picked from the file extension, Radiance HDR, PFM and OpenEXR keep
+// the linear radiance, everything else goes through the display transform to 8 bits
+pub fn save(img: &Rgb32FImage, path: &Path, display: &DisplayTransform) -> Result<(), RaytError> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    let image_error = |source| RaytError::Image {
+        path: path.to_path_buf(),
+        source,
+    };
+    let io_error = |source| RaytError::Io {
+        path: path.to_path_buf(),
+        source,
+    };
+
+    match extension.as_deref() {
+        Some("hdr") => {
+            let file = File::create(path).map_err(io_error)?;
+            let pixels: Vec<Rgb<f32>> = img.pixels().copied().collect();
+            HdrEncoder::new(BufWriter::new(file))
+                .encode(&pixels, img.width() as usize, img.height() as usize)
+                .map_err(image_error)
+        }
+        Some("pfm") => {
+            let file = File::create(path).map_err(io_error)?;
+            let mut writer = BufWriter::new(file);
+            write_pfm(img, &mut writer)
+                .and_then(|_| writer.flush())
+                .map_err(io_error)
+        }
+        Some("exr") => img.save(path).map_err(image_error),
+        _ => to_ldr(img, display).save(path).map_err(image_error),
+    }
+}
+
+// Portable Float Map: a text header followed by little endian floats, bottom row first
+fn write_pfm(img: &Rgb32FImage, writer: &mut impl Write) -> io::Result<()> {
+    write!(writer, "PF\n{} {}\n-1.0\n", img.width(), img.height())?;
+    for y in (0..img.height()).rev() {
+        for x in 0..img.width() {
+            for channel in img.get_pixel(x, y).0 {
+                writer.write_all(&channel.to_le_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, Rgb32FImage};
+
+    use super::write_pfm;
+
+    #[test]
+    fn pfm_keeps_radiance_above_one() {
+        let mut img = Rgb32FImage::new(2, 2);
+        img.put_pixel(0, 1, Rgb([4.5, 0.25, 1.0]));
+
+        let mut bytes = vec![];
+        write_pfm(&img, &mut bytes).unwrap();
+
+        let header = b"PF\n2 2\n-1.0\n";
+        assert_eq!(&bytes[..header.len()], header);
+        assert_eq!(bytes.len(), header.len() + 2 * 2 * 3 * 4);
+        let first = f32::from_le_bytes(bytes[header.len()..header.len() + 4].try_into().unwrap());
+        assert_eq!(first, 4.5);
+    }
+}
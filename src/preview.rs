@@ -0,0 +1,49 @@
+use image::{Rgb, Rgb32FImage};
+use indicatif::ProgressBar;
+use minifb::{Key, ScaleMode, Window, WindowOptions};
+
+use crate::{accumulator::Accumulator, error::RaytError, renderer::Renderer};
+
+// renders one sample per pixel per pass and shows the running average after every pass,
+// closing the window or pressing escape stops early and keeps what has been rendered so far
+pub fn render_preview(renderer: &Renderer) -> Result<Rgb32FImage, RaytError> {
+    renderer.check_image_size()?;
+    let width = renderer.camera.image_width;
+    let height = renderer.camera.image_height;
+    let samples = renderer.ctx.samples_per_pixel;
+
+    let mut window = Window::new(
+        "rayt",
+        width as usize,
+        height as usize,
+        WindowOptions {
+            resize: true,
+            scale_mode: ScaleMode::AspectRatioStretch,
+            ..WindowOptions::default()
+        },
+    )
+    .map_err(RaytError::Preview)?;
+
+    let mut acc = Accumulator::new(width, height);
+    let mut buffer = vec![0u32; (width * height) as usize];
+    let bar = ProgressBar::hidden();
+
+    while window.is_open() && !window.is_key_down(Key::Escape) && acc.samples() < samples {
+        renderer.render_pass(&mut acc, &bar);
+
+        for (target, color) in buffer.iter_mut().zip(acc.average()) {
+            let [r, g, b] = Rgb::from(renderer.display.apply(color)).0;
+            *target = (r as u32) << 16 | (g as u32) << 8 | b as u32;
+        }
+        window.set_title(&format!("rayt - {}/{} samples", acc.samples(), samples));
+        window
+            .update_with_buffer(&buffer, width as usize, height as usize)
+            .map_err(RaytError::Preview)?;
+    }
+
+    if renderer.ctx.output && acc.samples() < samples {
+        println!("Stopped after {} of {} samples", acc.samples(), samples);
+    }
+
+    Ok(acc.to_image())
+}
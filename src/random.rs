@@ -0,0 +1,23 @@
+use std::cell::RefCell;
+
+use rand::{rngs::SmallRng, Rng, SeedableRng};
+
+thread_local! {
+    static RNG: RefCell<SmallRng> = RefCell::new(SmallRng::from_entropy());
+}
+
+// every pixel and pass reseeds the generator of the thread rendering it, so the image only
+// depends on the render seed and not on how rayon schedules the rows
+pub fn reseed(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = SmallRng::seed_from_u64(seed));
+}
+
+pub fn pixel_seed(seed: u64, pass: u32, x: u32, y: u32) -> u64 {
+    let index = ((y as u64) << 32) | x as u64;
+    seed ^ index.wrapping_mul(0x9E37_79B9_7F4A_7C15)
+        ^ (pass as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F)
+}
+
+pub fn rand_f64() -> f64 {
+    RNG.with(|rng| rng.borrow_mut().gen())
+}
@@ -1,15 +1,28 @@
 use crate::maths::{Point3, Vec3};
 
+// distance kept from the surface a secondary or shadow ray leaves, so it does not hit it again
+pub const SHADOW_EPSILON: f64 = 0.0001;
+
 #[derive(Clone, Copy)]
 pub struct Ray {
     pub origin: Vec3,
     pub direction: Vec3,
+    // moment within the exposure, objects move from time 0 to time 1
+    pub time: f64,
 }
 
 impl Ray {
     pub fn new(origin: Point3, direction: Vec3) -> Self {
         let direction = direction.normalize();
-        Ray { origin, direction }
+        Ray {
+            origin,
+            direction,
+            time: 0.0,
+        }
+    }
+
+    pub fn with_time(self, time: f64) -> Self {
+        Ray { time, ..self }
     }
 
     pub fn at(&self, t: f64) -> Point3 {
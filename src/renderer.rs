@@ -1,32 +1,38 @@
-use image::{ImageBuffer, Rgb, RgbImage};
+use image::Rgb32FImage;
 use indicatif::{ProgressBar, ProgressStyle};
-use rand::Rng;
-use std::time::SystemTime;
+use std::time::Instant;
 
 use crate::{
-    camera::Camera, const_vars::ConstContext, hit::HittableList, light::LightGroup, maths::Color,
-    ray::Ray, shaders::ShaderType,
+    accumulator::Accumulator,
+    camera::Camera,
+    const_vars::ConstContext,
+    error::RaytError,
+    hit::Hittable,
+    light::LightGroup,
+    maths::Color,
+    random::{self, rand_f64},
+    ray::Ray,
+    shaders::ShaderType,
+    tonemap::DisplayTransform,
 };
 
 pub struct Renderer {
-    pub world: HittableList,
+    pub world: Box<dyn Hittable>,
     pub light_group: LightGroup,
     pub camera: Camera,
     pub ctx: ConstContext,
     pub shader_type: ShaderType,
-    pub probability_rr: f64,
-    pub gamma: f64,
+    pub display: DisplayTransform,
 }
 
 impl Renderer {
     pub fn new(
-        world: HittableList,
+        world: Box<dyn Hittable>,
         light_group: LightGroup,
         camera: Camera,
         ctx: ConstContext,
         shader_type: ShaderType,
-        probability_rr: f64,
-        gamma: f64,
+        display: DisplayTransform,
     ) -> Self {
         Self {
             world,
@@ -34,27 +40,59 @@ impl Renderer {
             camera,
             ctx,
             shader_type,
-            probability_rr,
-            gamma,
+            display,
         }
     }
 
     pub fn get_pixel_color(&self, ray: &Ray) -> Color {
         match self.shader_type {
-            ShaderType::PathTracing => self
-                .shader_path_tracing(ray, self.ctx.max_depth as i32)
-                .gamma_correction(self.gamma),
+            ShaderType::PathTracing => self.shader_path_tracing(ray),
             ShaderType::LeadTest => Color::new(0.0, 0.0, 0.0),
         }
     }
 
-    pub fn render(&self) -> ImageBuffer<Rgb<u8>, Vec<u8>> {
-        let mut img = RgbImage::new(self.camera.image_width, self.camera.image_height);
+    pub fn check_image_size(&self) -> Result<(), RaytError> {
+        if self.camera.image_width == 0 || self.camera.image_height == 0 {
+            return Err(RaytError::Validation {
+                key: if self.camera.image_width == 0 {
+                    "width"
+                } else {
+                    "height"
+                }
+                .to_string(),
+                line: None,
+                message: "image must not be empty".to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    // one sample for every pixel, each pixel and pass gets its own random sequence
+    pub fn render_pass(&self, acc: &mut Accumulator, bar: &ProgressBar) {
+        let width = self.camera.image_width;
+        let height = self.camera.image_height;
+        let pass = acc.samples();
+
+        acc.add_pass(
+            |x, y| {
+                random::reseed(random::pixel_seed(self.ctx.seed, pass, x, y));
+                self.camera
+                    .get_ray(width, height, x as f64 + rand_f64(), y as f64 + rand_f64())
+                    .map_or(Color::new(0.0, 0.0, 0.0), |ray| self.get_pixel_color(&ray))
+            },
+            || bar.inc(width.into()),
+        );
+    }
 
-        let start_t = SystemTime::now();
-        let mut rng = rand::thread_rng();
+    pub fn render(&self) -> Result<Rgb32FImage, RaytError> {
+        self.check_image_size()?;
+        let width = self.camera.image_width;
+        let height = self.camera.image_height;
+        let mut acc = Accumulator::new(width, height);
 
-        let bar = ProgressBar::new((self.camera.image_height * self.camera.image_width).into())
+        let start_t = Instant::now();
+
+        let bar = ProgressBar::new(u64::from(height * width) * u64::from(self.ctx.samples_per_pixel))
             .with_style(
                 ProgressStyle::with_template(
                     "{spinner:.green}  [{percent:.}%] [{elapsed_precise}] [{bar:60.cyan/blue}] {pos:>7.green}/{len:7.bold} {msg:>}",
@@ -63,35 +101,19 @@ impl Renderer {
                 .progress_chars("#>-"),
             );
 
-        // Draw pixels
-        for (x, y, pixel) in img.enumerate_pixels_mut() {
-            let y = self.camera.image_height as u32 - y - 1;
-            let mut pixel_color = Color::new(0.0, 0.0, 0.0);
-
-            for i in 0..self.ctx.samples_per_pixel {
-                let ray = self.camera.get_ray(
-                    self.camera.image_width,
-                    self.camera.image_height,
-                    x as f64 + rng.gen::<f64>(),
-                    y as f64 + rng.gen::<f64>(),
-                );
-
-                pixel_color = pixel_color + self.get_pixel_color(&ray);
-            }
-            pixel_color = pixel_color / self.ctx.samples_per_pixel as f64;
-
-            *pixel = pixel_color.into();
-            bar.inc(1);
+        // Draw pixels, one sample per pixel and pass
+        for _ in 0..self.ctx.samples_per_pixel {
+            self.render_pass(&mut acc, &bar);
         }
 
         bar.finish_and_clear();
 
-        let render_time = start_t.elapsed().unwrap().as_millis() as f64 / 1000.0;
+        let render_time = start_t.elapsed().as_millis() as f64 / 1000.0;
 
         if self.ctx.output {
             println!("Time elapsed: {}", render_time);
         }
 
-        img
+        Ok(acc.to_image())
     }
 }
@@ -0,0 +1,303 @@
+use core::f64::consts::PI;
+
+use crate::{
+    bsdf::BSDF,
+    maths::{Point3, Vec3},
+    random::rand_f64,
+};
+
+// orthonormal basis around `normal` (Duff et al., "Building an Orthonormal Basis, Revisited")
+fn basis(normal: Vec3) -> (Vec3, Vec3) {
+    let sign = 1f64.copysign(normal.z);
+    let a = -1.0 / (sign + normal.z);
+    let b = normal.x * normal.y * a;
+    let tangent = Vec3::new(
+        1.0 + sign * normal.x * normal.x * a,
+        sign * b,
+        -sign * normal.x,
+    );
+    let bitangent = Vec3::new(b, sign + normal.y * normal.y * a, -normal.y);
+    (tangent, bitangent)
+}
+
+pub fn local_to_world(local: Vec3, normal: Vec3) -> Vec3 {
+    let (tangent, bitangent) = basis(normal);
+    tangent * local.x + bitangent * local.y + normal * local.z
+}
+
+pub fn world_to_local(world: Vec3, normal: Vec3) -> Vec3 {
+    let (tangent, bitangent) = basis(normal);
+    Vec3::new(world * tangent, world * bitangent, world * normal)
+}
+
+// uniformly distributed unit vector, pdf = 1 / (4 pi)
+pub fn uniform_sphere() -> (Vec3, f64) {
+    let z = 1.0 - 2.0 * rand_f64();
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * rand_f64();
+    (Vec3::new(r * phi.cos(), r * phi.sin(), z), 1.0 / (4.0 * PI))
+}
+
+// uniform direction within `cos_max` of `axis`, pdf = 1 / (2 pi (1 - cos_max))
+pub fn uniform_cone(axis: Vec3, cos_max: f64) -> (Vec3, f64) {
+    let cos_theta = 1.0 - rand_f64() * (1.0 - cos_max);
+    let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+    let phi = 2.0 * PI * rand_f64();
+    let dir = local_to_world(
+        Vec3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta),
+        axis,
+    );
+    (dir, 1.0 / (2.0 * PI * (1.0 - cos_max)))
+}
+
+// cosine-weighted direction around `normal`, pdf = cos(theta) / pi
+pub fn cosine_hemisphere(normal: Vec3) -> (Vec3, f64) {
+    let (disk, _) = concentric_disk();
+    let z = (1.0 - disk.x * disk.x - disk.y * disk.y).max(0.0).sqrt();
+    let dir = local_to_world(Vec3::new(disk.x, disk.y, z), normal);
+    (dir, z / PI)
+}
+
+// point on the unit disk in the xy plane, Shirley's concentric mapping keeps strata compact,
+// pdf = 1 / pi per unit area
+pub fn concentric_disk() -> (Vec3, f64) {
+    let u = 2.0 * rand_f64() - 1.0;
+    let v = 2.0 * rand_f64() - 1.0;
+    if u == 0.0 && v == 0.0 {
+        return (Vec3::new(0.0, 0.0, 0.0), 1.0 / PI);
+    }
+    let (r, theta) = if u.abs() > v.abs() {
+        (u, PI / 4.0 * (v / u))
+    } else {
+        (v, PI / 2.0 - PI / 4.0 * (u / v))
+    };
+    (Vec3::new(r * theta.cos(), r * theta.sin(), 0.0), 1.0 / PI)
+}
+
+// point on a regular polygon with `sides` corners inscribed in the unit circle of the xy
+// plane, pdf = 1 / area
+pub fn regular_polygon(sides: u32) -> (Vec3, f64) {
+    let wedge = 2.0 * PI / sides as f64;
+    let index = ((rand_f64() * sides as f64) as u32).min(sides - 1) as f64;
+    let corner = |angle: f64| Vec3::new(angle.cos(), angle.sin(), 0.0);
+    let (point, _) = uniform_triangle([
+        Vec3::new(0.0, 0.0, 0.0),
+        corner(index * wedge),
+        corner((index + 1.0) * wedge),
+    ]);
+    let area = sides as f64 * wedge.sin() / 2.0;
+    (point, 1.0 / area)
+}
+
+// uniform point on a triangle, pdf = 1 / area
+pub fn uniform_triangle(vertices: [Point3; 3]) -> (Point3, f64) {
+    let [v0, v1, v2] = vertices;
+    let su = rand_f64().sqrt();
+    let b0 = 1.0 - su;
+    let b1 = rand_f64() * su;
+    let point = v0 * b0 + v1 * b1 + v2 * (1.0 - b0 - b1);
+    let area = (v1 - v0).cross(&(v2 - v0)).length() / 2.0;
+    (point, 1.0 / area)
+}
+
+// GGX microfacet normal drawn from the normals visible from `wo` (Heitz 2018, "Sampling the
+// GGX Distribution of Visible Normals"), returns it with `ggx_vndf_pdf`
+pub fn ggx_vndf(normal: Vec3, wo: Vec3, alpha: f64) -> (Vec3, f64) {
+    let local = world_to_local(wo, normal);
+    // stretch the view direction into the hemisphere configuration
+    let vh = Vec3::new(alpha * local.x, alpha * local.y, local.z).normalize();
+    let len_sq = vh.x * vh.x + vh.y * vh.y;
+    let t1 = if len_sq > 0.0 {
+        Vec3::new(-vh.y, vh.x, 0.0) / len_sq.sqrt()
+    } else {
+        Vec3::new(1.0, 0.0, 0.0)
+    };
+    let t2 = vh.cross(&t1);
+
+    // uniform point on the projected half disk
+    let r = rand_f64().sqrt();
+    let phi = 2.0 * PI * rand_f64();
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1.0 + vh.z);
+    let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+    let nh = t1 * p1 + t2 * p2 + vh * (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt();
+
+    // unstretch back to the ellipsoid
+    let h = Vec3::new(alpha * nh.x, alpha * nh.y, nh.z.max(1e-6)).normalize();
+    let h = local_to_world(h, normal);
+    (h, ggx_vndf_pdf(normal, wo, h, alpha))
+}
+
+// D_wo(h) = G1(wo) max(0, wo.h) D(h) / (n.wo)
+pub fn ggx_vndf_pdf(normal: Vec3, wo: Vec3, h: Vec3, alpha: f64) -> f64 {
+    let cos_o = wo * normal;
+    let cos_oh = wo * h;
+    if cos_o <= 0.0 || cos_oh <= 0.0 {
+        return 0.0;
+    }
+    BSDF::smith_g1(cos_o, alpha) * cos_oh * BSDF::ggx_distribution(normal * h, alpha) / cos_o
+}
+
+// MIS weight of a strategy with density `f_pdf` against one with density `g_pdf` (Veach, beta = 2)
+pub fn power_heuristic(f_pdf: f64, g_pdf: f64) -> f64 {
+    let f = f_pdf * f_pdf;
+    let g = g_pdf * g_pdf;
+    if f + g == 0.0 {
+        return 0.0;
+    }
+    f / (f + g)
+}
+
+// piecewise-constant density over [0, 1) proportional to `func`
+pub struct Distribution1D {
+    func: Vec<f64>,
+    cdf: Vec<f64>,
+    integral: f64,
+}
+
+impl Distribution1D {
+    pub fn new(func: Vec<f64>) -> Self {
+        let n = func.len() as f64;
+        let mut cdf = Vec::with_capacity(func.len() + 1);
+        cdf.push(0.0);
+        for value in &func {
+            cdf.push(cdf.last().unwrap() + value / n);
+        }
+        let integral = *cdf.last().unwrap();
+        // an all-black function falls back to a uniform density
+        for (i, c) in cdf.iter_mut().enumerate() {
+            *c = if integral > 0.0 {
+                *c / integral
+            } else {
+                i as f64 / n
+            };
+        }
+        Self {
+            func,
+            cdf,
+            integral,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.func.len()
+    }
+
+    pub fn integral(&self) -> f64 {
+        self.integral
+    }
+
+    // continuous sample in [0, 1), its density and the segment it fell in
+    pub fn sample(&self, u: f64) -> (f64, f64, usize) {
+        let index = (self.cdf.partition_point(|&c| c <= u) - 1).min(self.len() - 1);
+        let width = self.cdf[index + 1] - self.cdf[index];
+        let offset = if width > 0.0 {
+            (u - self.cdf[index]) / width
+        } else {
+            0.0
+        };
+        let x = (index as f64 + offset) / self.len() as f64;
+        (x.min(1.0 - f64::EPSILON), self.pdf(index), index)
+    }
+
+    pub fn pdf(&self, index: usize) -> f64 {
+        if self.integral > 0.0 {
+            self.func[index] / self.integral
+        } else {
+            1.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::f64::consts::PI;
+
+    use super::*;
+    use crate::random;
+
+    // E[f / pdf] over the samples is the integral of f over the directions the sampler covers,
+    // it only comes out right when the samples are distributed as the pdf claims
+    fn integrate(n: usize, sampler: impl Fn() -> (Vec3, f64), f: impl Fn(Vec3) -> f64) -> f64 {
+        (0..n)
+            .map(|_| {
+                let (dir, pdf) = sampler();
+                f(dir) / pdf
+            })
+            .sum::<f64>()
+            / n as f64
+    }
+
+    #[test]
+    fn direction_pdfs_are_normalized() {
+        random::reseed(23);
+        let normal = Vec3::new(0.3, -0.5, 0.8).normalize();
+        let n = 200000;
+        let close = |value: f64, expected: f64| (value - expected).abs() < 0.02 * expected;
+
+        // the integral of cos^2 is 4 pi / 3 over the sphere and 2 pi / 3 over a hemisphere
+        let cos2 = |dir: Vec3| (dir * normal).powi(2);
+        let sphere = integrate(n, uniform_sphere, cos2);
+        assert!(close(sphere, 4.0 * PI / 3.0), "{}", sphere);
+        let cosine = integrate(n, || cosine_hemisphere(normal), cos2);
+        assert!(close(cosine, 2.0 * PI / 3.0), "{}", cosine);
+
+        // the cosine over a cone is pi (1 - cos_max^2), and it covers 2 pi (1 - cos_max)
+        let cone = integrate(n, || uniform_cone(normal, 0.5), |dir| dir * normal);
+        assert!(close(cone, PI * 0.75), "{}", cone);
+        let cone = integrate(n, || uniform_cone(normal, 0.5), |_| 1.0);
+        assert!(close(cone, PI), "{}", cone);
+
+        for _ in 0..1000 {
+            assert!(cosine_hemisphere(normal).0 * normal >= 0.0);
+            assert!(uniform_cone(normal, 0.9).0 * normal >= 0.9 - 1e-9);
+        }
+    }
+
+    #[test]
+    fn vndf_pdf_integrates_to_one() {
+        random::reseed(29);
+        let normal = Vec3::new(0.0, 1.0, 0.0);
+        let wo = Vec3::new(0.6, 0.5, 0.2).normalize();
+        let alpha = 0.5;
+
+        // integrate the pdf over the hemisphere with uniformly distributed normals
+        let n = 200000;
+        let mut integral = 0.0;
+        for _ in 0..n {
+            let (h, _) = uniform_sphere();
+            let h = if h * normal < 0.0 { h * (-1.0) } else { h };
+            integral += ggx_vndf_pdf(normal, wo, h, alpha) * 2.0 * PI;
+        }
+        integral /= n as f64;
+        assert!((integral - 1.0).abs() < 0.03, "{}", integral);
+
+        for _ in 0..1000 {
+            let (h, pdf) = ggx_vndf(normal, wo, alpha);
+            assert!(h * wo > 0.0 && pdf > 0.0);
+        }
+    }
+
+    #[test]
+    fn area_samples_stay_inside() {
+        random::reseed(31);
+        let vertices = [
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        ];
+        let n = 100000;
+        let mut mean = Vec3::new(0.0, 0.0, 0.0);
+        for _ in 0..n {
+            let (p, pdf) = uniform_triangle(vertices);
+            assert_eq!(pdf, 1.0);
+            assert!(p.x >= 0.0 && p.y >= 0.0 && p.x / 2.0 + p.y <= 1.0 + 1e-9);
+            mean = mean + p / n as f64;
+
+            let (d, _) = concentric_disk();
+            assert!(d.x * d.x + d.y * d.y <= 1.0 + 1e-9);
+        }
+        // uniform samples average to the centroid
+        assert!((mean - Vec3::new(2.0 / 3.0, 1.0 / 3.0, 0.0)).length() < 0.01);
+    }
+}
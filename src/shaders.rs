@@ -1,91 +1,380 @@
-use rand::{thread_rng, Rng};
-use std::f64::INFINITY;
+use std::str::FromStr;
 
-use crate::bsdf::BSDF;
+use crate::error::RaytError;
+use crate::random::rand_f64;
+use crate::sampling::power_heuristic;
 use crate::{
-    hit::Hittable,
+    hit::HitRecord,
     light::*,
-    maths::{Vec3, HDR},
-    ray::Ray,
+    maths::{Point3, Vec3, HDR},
+    ray::{Ray, SHADOW_EPSILON},
     renderer::Renderer,
 };
 
+// bounces that are never terminated by Russian roulette
+const RR_MIN_DEPTH: u32 = 3;
+
 pub enum ShaderType {
     PathTracing,
     LeadTest,
 }
 
+impl FromStr for ShaderType {
+    type Err = RaytError;
+
+    fn from_str(s: &str) -> Result<Self, RaytError> {
+        match s {
+            "PathTracing" => Ok(ShaderType::PathTracing),
+            "LeadTest" => Ok(ShaderType::LeadTest),
+            _ => Err(RaytError::UnknownShader {
+                name: s.to_string(),
+                line: None,
+            }),
+        }
+    }
+}
+
 impl Renderer {
-    pub fn shader_path_tracing(&self, ray: &Ray, depth: i32) -> HDR {
-        let mut total_emmision = HDR::new(0.0, 0.0, 0.0);
-        if depth < self.ctx.max_depth as i32 {
-            for light in &self.light_group.lights {
-                if let Light::SunLight(sunlight) = light {
-                    let check_ray = Ray::new(ray.origin, sunlight.direction * (-1.0));
-                    if let None = self.world.get_hit_record(&check_ray, 0.0001, INFINITY) {
-                        total_emmision = total_emmision + sunlight.color * sunlight.intensity;
-                    }
+    // shadow ray test between a surface point and a light `distance` away along `wi`
+    fn is_visible(&self, point: Point3, wi: Vec3, distance: f64, time: f64) -> bool {
+        let shadow_ray = Ray::new(point, wi).with_time(time);
+        self.world
+            .get_hit_record(&shadow_ray, SHADOW_EPSILON, distance - SHADOW_EPSILON)
+            .is_none()
+    }
+
+    pub fn shader_path_tracing(&self, ray: &Ray) -> HDR {
+        let mut radiance = HDR::new(0.0, 0.0, 0.0);
+        let mut throughput = HDR::new(1.0, 1.0, 1.0);
+        let mut ray = *ray;
+        // density the previous vertex sampled `ray` with, None for camera rays and specular
+        // bounces which light sampling cannot reproduce
+        let mut bsdf_pdf = None;
+        let mut depth = 0;
+
+        loop {
+            let record = self.world.get_hit_record(&ray, SHADOW_EPSILON, f64::INFINITY);
+            radiance = radiance + throughput.mix(self.emitted(&ray, record.as_ref(), bsdf_pdf));
+
+            let mut record = match record {
+                Some(record) if depth < self.ctx.max_depth => record,
+                _ => break,
+            };
+            let material = record.material;
+            material.perturb_normal(&mut record);
+
+            let wo = ray.direction * (-1.0);
+            radiance = radiance + throughput.mix(self.sample_lights(wo, &record, ray.time));
+
+            let sample = match record.material.sample(wo, &record) {
+                Some(sample) if sample.pdf > 0.0 && record.is_consistent(sample.wi) => sample,
+                _ => break,
+            };
+            let cos_theta = (sample.wi * record.normal).abs();
+            throughput = throughput.mix(sample.f * (cos_theta / sample.pdf));
+
+            // paths carrying little energy are ended early, survivors are boosted to
+            // keep the estimate unbiased
+            if depth >= RR_MIN_DEPTH {
+                let survival = throughput.max_component().min(0.95);
+                if rand_f64() >= survival {
+                    break;
                 }
+                throughput = throughput / survival;
             }
+
+            ray = Ray::new(record.point, sample.wi).with_time(ray.time);
+            bsdf_pdf = if sample.specular {
+                None
+            } else {
+                Some(sample.pdf)
+            };
+            depth += 1;
         }
-        if depth <= 0 {
-            return total_emmision / self.probability_rr;
+        radiance
+    }
+
+    // radiance of the lights `ray` passes before reaching `record`, lights do not occlude each
+    // other or the scene so they are found separately from the surfaces, and of the surface
+    // itself when its material glows
+    fn emitted(&self, ray: &Ray, record: Option<&HitRecord>, bsdf_pdf: Option<f64>) -> HDR {
+        let weight = |light_pdf: f64| bsdf_pdf.map_or(1.0, |pdf| power_heuristic(pdf, light_pdf));
+        let t_max = record.map_or(f64::INFINITY, |record| record.t);
+        let mut total = HDR::new(0.0, 0.0, 0.0);
+
+        if let Some(record) = record {
+            let emission = record.material.emitted(record);
+            if emission != HDR::new(0.0, 0.0, 0.0) {
+                // surfaces no light was made from are only found by bsdf sampling and keep
+                // their full weight
+                let cos_light = (ray.direction * record.geometric_normal).abs();
+                let light_pdf = self.light_group.lights.iter().find_map(|light| match light {
+                    Light::EmissiveLight(light) if light.contains(record.obj) => {
+                        Some(light.pdf(record.t, cos_light))
+                    }
+                    _ => None,
+                });
+                total = total + emission * light_pdf.map_or(1.0, weight);
+            }
         }
-        match self.world.get_hit_record(&ray, 0.0001, INFINITY) {
-            Some(record) => {
-                let mut rng = thread_rng();
-                let rand = rng.gen::<f64>();
-                if rand > self.probability_rr {
-                    return total_emmision / self.probability_rr;
-                }
-                let rand_dir = Vec3::rand_hemisphere_dir(record.normal);
-                let rand_ray = Ray::new(record.point, rand_dir);
-                let wi = rand_dir;
-                let wo = ray.direction * (-1.0);
-                let mut light_contrib = HDR::new(0.0, 0.0, 0.0);
-                for light in &self.light_group.lights {
-                    if let Light::SunLight(sunlight) = light {
-                        let check_ray = Ray::new(ray.origin, sunlight.direction * (-1.0));
-                        if let None = self.world.get_hit_record(&check_ray, 0.0001, INFINITY) {
-                            light_contrib = light_contrib
-                                + (sunlight.color * sunlight.intensity).mix(
-                                    BSDF::cook_torrance_brdf(
-                                        record.obj.get_roughness(),
-                                        record.obj.get_reflectivity(),
-                                        record.obj.get_roughness(),
-                                        wo,
-                                        wi,
-                                        record.normal,
-                                        record.obj.get_color(),
-                                    ),
-                                ) / self.probability_rr;
-                        }
+
+        for light in &self.light_group.lights {
+            match light {
+                Light::AreaLight(area_light) => {
+                    if let Some((_, light_pdf)) = area_light.hit(ray, t_max) {
+                        total = total
+                            + area_light.color * (area_light.intensity * weight(light_pdf));
                     }
                 }
-                return light_contrib
-                    + BSDF::cook_torrance_brdf(
-                        record.obj.get_roughness(),
-                        record.obj.get_reflectivity(),
-                        record.obj.get_roughness(),
-                        wo,
-                        wi,
-                        record.normal,
-                        record.obj.get_color(),
-                    )
-                    .mix(self.shader_path_tracing(&rand_ray, depth - 1))
-                        / self.probability_rr;
+                Light::HDRILight(hdrilight) if t_max == f64::INFINITY => {
+                    total = total
+                        + hdrilight.get_hdr_value(ray.direction)
+                            * weight(hdrilight.pdf(ray.direction));
+                }
+                _ => {}
             }
-            None => {
-                for light in &self.light_group.lights {
-                    if let Light::HDRILight(hdrilight) = light {
-                        if let None = self.world.get_hit_record(ray, 0.0001, INFINITY) {
-                            total_emmision =
-                                total_emmision + hdrilight.get_hdr_value(ray.direction);
-                        }
-                    }
+        }
+        total
+    }
+
+    // next event estimation, one shadow ray towards every light weighted against the bsdf
+    fn sample_lights(&self, wo: Vec3, record: &HitRecord, time: f64) -> HDR {
+        let mut total = HDR::new(0.0, 0.0, 0.0);
+        for light in &self.light_group.lights {
+            let light_sample = match light.sample(record.point, time) {
+                Some(light_sample) if light_sample.pdf > 0.0 => light_sample,
+                _ => continue,
+            };
+            let wi = light_sample.wi;
+            let f = record.material.eval(wo, wi, record);
+            if f == HDR::new(0.0, 0.0, 0.0)
+                || !record.is_consistent(wi)
+                || !self.is_visible(record.point, wi, light_sample.distance, time)
+            {
+                continue;
+            }
+            let weight = if light_sample.delta {
+                1.0
+            } else {
+                power_heuristic(light_sample.pdf, record.material.pdf(wo, wi, record))
+            };
+            let cos_theta = (wi * record.normal).abs();
+            total = total + light_sample.radiance.mix(f) * (cos_theta * weight / light_sample.pdf);
+        }
+        total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::ShaderType;
+    use crate::{
+        camera::{Camera, SerializationCamera},
+        cli::Config,
+        const_vars::ConstContext,
+        hit::{Hittable, HittableList, Surface},
+        light::{EmissiveLight, HDRILight, Light, LightGroup},
+        material::{Diffuse, Emissive, Material},
+        maths::{Color, Quaternion, Vec3},
+        objects::{instance::Transform, Instance, Plane, Sphere},
+        random::{self, rand_f64},
+        ray::Ray,
+        renderer::Renderer,
+        tonemap::DisplayTransform,
+    };
+
+    fn renderer(world: Box<dyn Hittable>, light_group: LightGroup) -> Renderer {
+        let config: Config = toml::from_str(
+            "width = 1\nheight = 1\nsamples = 1\nmax_depth = 16\nrender_type = \"PathTracing\"",
+        )
+        .unwrap();
+        let ctx = ConstContext {
+            samples_per_pixel: 1,
+            max_depth: 16,
+            output: false,
+            preview: false,
+            seed: 0,
+            config,
+        };
+        Renderer::new(
+            world,
+            light_group,
+            Camera::new(1, 1, &SerializationCamera::default()),
+            ctx,
+            ShaderType::PathTracing,
+            DisplayTransform::default(),
+        )
+    }
+
+    // a white furnace: a unit-albedo object under a uniform environment is invisible
+    #[test]
+    fn white_furnace() {
+        let white: Arc<dyn Material> = Arc::new(Diffuse::new(Color::new(1.0, 1.0, 1.0)));
+        let mut world = HittableList::new();
+        world.add(Arc::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, white)));
+
+        let environment = Color::new(0.2, 0.5, 0.8);
+        let mut light_group = LightGroup::new();
+        light_group.add(Light::HDRILight(HDRILight::new(environment, 1.0)));
+
+        let renderer = renderer(Box::new(world), light_group);
+
+        random::reseed(17);
+        let n = 50000;
+        let mut total = Color::new(0.0, 0.0, 0.0);
+        for _ in 0..n {
+            // aim somewhere on the sphere
+            let target = Vec3::new(rand_f64() - 0.5, rand_f64() - 0.5, 0.0);
+            let origin = Vec3::new(0.0, 0.0, 4.0);
+            total = total + renderer.shader_path_tracing(&Ray::new(origin, target - origin));
+        }
+        let average = total / n as f64;
+        for i in 0..3 {
+            assert!(
+                (average[i] - environment[i]).abs() < 0.01 * environment[i],
+                "{} {}",
+                average[i],
+                environment[i]
+            );
+        }
+    }
+
+    // a diffuse floor under an emissive sphere straight above the shaded point receives
+    // E = pi L (r / h)^2 and reflects albedo L (r / h)^2, whether the sphere is sampled as a
+    // light or only found by bsdf sampling
+    #[test]
+    fn emissive_sphere_lights_a_floor() {
+        let (radiance, radius, height, albedo) = (4.0, 0.5, 2.0, 0.5);
+        let black: Arc<dyn Material> = Arc::new(Diffuse::new(Color::new(0.0, 0.0, 0.0)));
+        let glow: Arc<dyn Material> =
+            Arc::new(Emissive::new(black, Color::new(radiance, radiance, radiance)));
+        let grey: Arc<dyn Material> = Arc::new(Diffuse::new(Color::new(albedo, albedo, albedo)));
+        let sphere = Arc::new(Sphere::new(Vec3::new(0.0, height, 0.0), radius, glow));
+        let floor = Arc::new(Plane::new(
+            Vec3::new(-10.0, 0.0, -10.0),
+            Vec3::new(0.0, 0.0, 20.0),
+            Vec3::new(20.0, 0.0, 0.0),
+            grey,
+        ));
+        let expected = albedo * radiance * (radius / height).powi(2);
+
+        for sample_lights in [true, false] {
+            let mut world = HittableList::new();
+            world.add(sphere.clone());
+            world.add(floor.clone());
+            let mut light_group = LightGroup::new();
+            if sample_lights {
+                let emission = Color::new(radiance, radiance, radiance);
+                light_group.add(Light::EmissiveLight(EmissiveLight::new(
+                    emission,
+                    vec![sphere.clone()],
+                )));
+            }
+            let renderer = renderer(Box::new(world), light_group);
+
+            random::reseed(23);
+            let n = 200000;
+            let mut total = 0.0;
+            for _ in 0..n {
+                // a grazing view of the shaded point that passes well clear of the sphere
+                let origin = Vec3::new(3.0, 1.0, 0.0);
+                total += renderer.shader_path_tracing(&Ray::new(origin, origin * (-1.0))).x;
+            }
+            let average = total / n as f64;
+            assert!(
+                (average - expected).abs() < 0.03 * expected,
+                "{} {} {}",
+                sample_lights,
+                average,
+                expected
+            );
+        }
+    }
+
+    fn place<T: Hittable + Surface + 'static>(
+        object: Arc<T>,
+        translation: Vec3,
+        scale: Vec3,
+        flat: bool,
+    ) -> (Arc<Instance>, bool) {
+        let transform = Transform {
+            translation,
+            rotation: Quaternion::from_axis_angle(Vec3::new(0.0, 1.0, 0.0), 0.3),
+            scale,
+        };
+        let instance = Instance::new(object.clone(), transform).with_surfaces(vec![object]);
+        let uniform = instance.samples_uniformly(flat);
+        (Arc::new(instance), uniform)
+    }
+
+    // unevenly scaled emissive instances: the stretched square is sampled as a light, the
+    // squashed sphere cannot be sampled uniformly by area and is left to bsdf sampling, as
+    // scene files do, and both have to match bsdf sampling alone
+    #[test]
+    fn scaled_emissive_instances_match_bsdf_sampling() {
+        let radiance = Color::new(4.0, 4.0, 4.0);
+        let black: Arc<dyn Material> = Arc::new(Diffuse::new(Color::new(0.0, 0.0, 0.0)));
+        let glow: Arc<dyn Material> = Arc::new(Emissive::new(black, radiance));
+        let grey: Arc<dyn Material> = Arc::new(Diffuse::new(Color::new(0.5, 0.5, 0.5)));
+        let floor = Arc::new(Plane::new(
+            Vec3::new(-10.0, 0.0, -10.0),
+            Vec3::new(0.0, 0.0, 20.0),
+            Vec3::new(20.0, 0.0, 0.0),
+            grey,
+        ));
+        // a unit square facing down and a unit sphere
+        let square = Arc::new(Plane::new(
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(0.0, 0.0, 1.0),
+            glow.clone(),
+        ));
+        let sphere = Arc::new(Sphere::new(Vec3::new(0.0, 0.0, 0.0), 1.0, glow));
+        let instances = [
+            place(
+                square.clone(),
+                Vec3::new(-1.0, 1.5, -0.25),
+                Vec3::new(2.0, 1.0, 0.5),
+                true,
+            ),
+            place(
+                sphere.clone(),
+                Vec3::new(1.2, 0.8, 0.6),
+                Vec3::new(0.6, 0.3, 0.4),
+                false,
+            ),
+        ];
+        assert!(instances[0].1 && !instances[1].1);
+
+        let mut averages = vec![];
+        for sample_lights in [true, false] {
+            let mut world = HittableList::new();
+            world.add(floor.clone());
+            let mut surfaces: Vec<Arc<dyn Surface>> = vec![];
+            for (instance, uniform) in &instances {
+                world.add(instance.clone());
+                if sample_lights && *uniform {
+                    surfaces.push(instance.clone());
                 }
-                return total_emmision / self.probability_rr;
             }
+            let mut light_group = LightGroup::new();
+            if !surfaces.is_empty() {
+                light_group.add(Light::EmissiveLight(EmissiveLight::new(radiance, surfaces)));
+            }
+            let renderer = renderer(Box::new(world), light_group);
+
+            random::reseed(29);
+            let n = 200000;
+            let mut total = 0.0;
+            for _ in 0..n {
+                let origin = Vec3::new(-3.0, 1.0, 0.0);
+                total += renderer.shader_path_tracing(&Ray::new(origin, origin * (-1.0))).x;
+            }
+            averages.push(total / n as f64);
         }
+        let (nee, bsdf) = (averages[0], averages[1]);
+        assert!((nee - bsdf).abs() < 0.02 * bsdf, "{} {}", nee, bsdf);
     }
 }
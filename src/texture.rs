@@ -0,0 +1,580 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use image::ColorType;
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use serde::Deserialize;
+
+use crate::{
+    error::{check_color, FieldError, RaytError, Validate},
+    hit::HitRecord,
+    maths::{Color, Point3},
+};
+
+// varies a material parameter over a surface, from the texture coordinates or the hit point
+pub trait Texture: Send + Sync {
+    fn value(&self, uv: (f64, f64), point: Point3) -> Color;
+}
+
+// how image lookups outside the unit square are folded back into it
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub enum Wrap {
+    #[default]
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+impl Wrap {
+    fn texel(self, i: i64, size: usize) -> usize {
+        let size = size as i64;
+        let i = match self {
+            Wrap::Repeat => i.rem_euclid(size),
+            Wrap::Clamp => i.clamp(0, size - 1),
+            Wrap::Mirror => {
+                let i = i.rem_euclid(2 * size);
+                if i < size {
+                    i
+                } else {
+                    2 * size - 1 - i
+                }
+            }
+        };
+        i as usize
+    }
+}
+
+fn srgb_to_linear(c: f64) -> f64 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// bilinearly filtered image, v runs from the bottom row up
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+    wrap: Wrap,
+}
+
+impl ImageTexture {
+    // 8 and 16 bit images are decoded from sRGB unless they hold `linear` data such as roughness
+    pub fn load(path: &Path, wrap: Wrap, linear: bool) -> Result<Self, RaytError> {
+        let img = image::open(path).map_err(|source| RaytError::Image {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let decode = !linear && !matches!(img.color(), ColorType::Rgb32F | ColorType::Rgba32F);
+        let img = img.into_rgb32f();
+        let pixels = img
+            .pixels()
+            .map(|p| {
+                let c = Color::new(p[0] as f64, p[1] as f64, p[2] as f64);
+                if decode {
+                    Color::new(
+                        srgb_to_linear(c.x),
+                        srgb_to_linear(c.y),
+                        srgb_to_linear(c.z),
+                    )
+                } else {
+                    c
+                }
+            })
+            .collect();
+        Ok(Self::new(
+            img.width() as usize,
+            img.height() as usize,
+            pixels,
+            wrap,
+        ))
+    }
+
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>, wrap: Wrap) -> Self {
+        Self {
+            width,
+            height,
+            pixels,
+            wrap,
+        }
+    }
+
+    fn pixel(&self, x: i64, y: i64) -> Color {
+        let x = self.wrap.texel(x, self.width);
+        let y = self.wrap.texel(y, self.height);
+        self.pixels[y * self.width + x]
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, (u, v): (f64, f64), _point: Point3) -> Color {
+        // pixel centers sit at half integer coordinates
+        let x = u * self.width as f64 - 0.5;
+        let y = (1.0 - v) * self.height as f64 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let top = self.pixel(x0, y0) * (1.0 - fx) + self.pixel(x0 + 1, y0) * fx;
+        let bottom = self.pixel(x0, y0 + 1) * (1.0 - fx) + self.pixel(x0 + 1, y0 + 1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+}
+
+// solid checkerboard of cubes `1 / scale` wide, independent of the texture coordinates
+pub struct Checker {
+    even: Color,
+    odd: Color,
+    scale: f64,
+}
+
+impl Checker {
+    pub fn new(even: Color, odd: Color, scale: f64) -> Self {
+        Self { even, odd, scale }
+    }
+}
+
+impl Texture for Checker {
+    fn value(&self, _uv: (f64, f64), point: Point3) -> Color {
+        let p = point * self.scale;
+        // a little offset keeps surfaces lying on integer coordinates off the cell borders
+        let cells = [p.x, p.y, p.z]
+            .iter()
+            .map(|c| (c + 1e-6).floor() as i64)
+            .sum::<i64>();
+        if cells.rem_euclid(2) == 0 {
+            self.even
+        } else {
+            self.odd
+        }
+    }
+}
+
+// improved gradient noise (Perlin 2002) over a shuffled permutation table
+pub struct Perlin {
+    permutation: [usize; 512],
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Self {
+        let mut table: Vec<usize> = (0..256).collect();
+        table.shuffle(&mut StdRng::seed_from_u64(seed));
+        let mut permutation = [0; 512];
+        for (i, p) in permutation.iter_mut().enumerate() {
+            *p = table[i % 256];
+        }
+        Self { permutation }
+    }
+
+    fn fade(t: f64) -> f64 {
+        t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+    }
+
+    // dot product with one of the 12 edge directions of a cube
+    fn gradient(hash: usize, x: f64, y: f64, z: f64) -> f64 {
+        let h = hash & 15;
+        let u = if h < 8 { x } else { y };
+        let v = match h {
+            0..=3 => y,
+            12 | 14 => x,
+            _ => z,
+        };
+        (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+    }
+
+    fn lerp(t: f64, a: f64, b: f64) -> f64 {
+        a + t * (b - a)
+    }
+
+    // roughly in [-1, 1] and zero on the integer lattice
+    pub fn noise(&self, point: Point3) -> f64 {
+        let p = &self.permutation;
+        let (fx, fy, fz) = (point.x.floor(), point.y.floor(), point.z.floor());
+        let (xi, yi, zi) = (
+            (fx as i64).rem_euclid(256) as usize,
+            (fy as i64).rem_euclid(256) as usize,
+            (fz as i64).rem_euclid(256) as usize,
+        );
+        let (x, y, z) = (point.x - fx, point.y - fy, point.z - fz);
+        let (u, v, w) = (Self::fade(x), Self::fade(y), Self::fade(z));
+
+        let a = p[xi] + yi;
+        let (aa, ab) = (p[a] + zi, p[a + 1] + zi);
+        let b = p[xi + 1] + yi;
+        let (ba, bb) = (p[b] + zi, p[b + 1] + zi);
+
+        Self::lerp(
+            w,
+            Self::lerp(
+                v,
+                Self::lerp(
+                    u,
+                    Self::gradient(p[aa], x, y, z),
+                    Self::gradient(p[ba], x - 1.0, y, z),
+                ),
+                Self::lerp(
+                    u,
+                    Self::gradient(p[ab], x, y - 1.0, z),
+                    Self::gradient(p[bb], x - 1.0, y - 1.0, z),
+                ),
+            ),
+            Self::lerp(
+                v,
+                Self::lerp(
+                    u,
+                    Self::gradient(p[aa + 1], x, y, z - 1.0),
+                    Self::gradient(p[ba + 1], x - 1.0, y, z - 1.0),
+                ),
+                Self::lerp(
+                    u,
+                    Self::gradient(p[ab + 1], x, y - 1.0, z - 1.0),
+                    Self::gradient(p[bb + 1], x - 1.0, y - 1.0, z - 1.0),
+                ),
+            ),
+        )
+    }
+
+    // fractional Brownian motion, each octave doubles the frequency and halves the amplitude
+    pub fn fbm(&self, point: Point3, octaves: u32) -> f64 {
+        let mut sum = 0.0;
+        let mut norm = 0.0;
+        let mut amplitude = 1.0;
+        let mut p = point;
+        for _ in 0..octaves {
+            sum += amplitude * self.noise(p);
+            norm += amplitude;
+            amplitude *= 0.5;
+            p = p * 2.0;
+        }
+        sum / norm
+    }
+}
+
+// solid noise blending from `color0` to `color1`, `octaves` above one sum finer copies of the
+// Perlin noise on top of it, textures with different seeds get unrelated patterns
+pub struct Noise {
+    perlin: Perlin,
+    color0: Color,
+    color1: Color,
+    scale: f64,
+    octaves: u32,
+}
+
+impl Noise {
+    pub fn new(color0: Color, color1: Color, scale: f64, octaves: u32, seed: u64) -> Self {
+        Self {
+            perlin: Perlin::new(seed),
+            color0,
+            color1,
+            scale,
+            octaves,
+        }
+    }
+}
+
+impl Texture for Noise {
+    fn value(&self, _uv: (f64, f64), point: Point3) -> Color {
+        let n = self.perlin.fbm(point * self.scale, self.octaves);
+        let t = (0.5 * (n + 1.0)).clamp(0.0, 1.0);
+        self.color0 * (1.0 - t) + self.color1 * t
+    }
+}
+
+// a material parameter that is either the same everywhere or read from a texture
+#[derive(Clone)]
+pub enum Parameter<T> {
+    Constant(T),
+    Texture(Arc<dyn Texture>),
+}
+
+impl<T> From<T> for Parameter<T> {
+    fn from(value: T) -> Self {
+        Parameter::Constant(value)
+    }
+}
+
+impl Parameter<Color> {
+    pub fn value(&self, rec: &HitRecord) -> Color {
+        match self {
+            Parameter::Constant(value) => *value,
+            Parameter::Texture(texture) => texture.value(rec.uv, rec.object.point),
+        }
+    }
+}
+
+impl Parameter<f64> {
+    // scalar parameters read the brightness of a texture
+    pub fn value(&self, rec: &HitRecord) -> f64 {
+        match self {
+            Parameter::Constant(value) => *value,
+            Parameter::Texture(texture) => texture.value(rec.uv, rec.object.point).luminance(),
+        }
+    }
+}
+
+// either a constant such as `[0.8, 0.2, 0.2]` or `0.5`, or the name of a [[Texture]]
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum SerializationParameter<T> {
+    Constant(T),
+    Texture(String),
+}
+
+impl<T: Copy> SerializationParameter<T> {
+    pub fn constant(&self) -> Option<T> {
+        match self {
+            SerializationParameter::Constant(value) => Some(*value),
+            SerializationParameter::Texture(_) => None,
+        }
+    }
+
+    pub fn texture(&self) -> Option<&str> {
+        match self {
+            SerializationParameter::Constant(_) => None,
+            SerializationParameter::Texture(name) => Some(name),
+        }
+    }
+
+    pub fn resolve<U: From<T>>(&self, textures: &TextureLibrary) -> Parameter<U> {
+        match self {
+            SerializationParameter::Constant(value) => Parameter::Constant((*value).into()),
+            SerializationParameter::Texture(name) => Parameter::Texture(textures.get(name)),
+        }
+    }
+}
+
+fn default_scale() -> f64 {
+    1.0
+}
+
+fn default_octaves() -> u32 {
+    1
+}
+
+fn default_color1() -> (f64, f64, f64) {
+    (1.0, 1.0, 1.0)
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SerializationTexture {
+    name: String,
+    #[serde(flatten)]
+    kind: SerializationTextureKind,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type")]
+enum SerializationTextureKind {
+    Image {
+        path: PathBuf,
+        #[serde(default)]
+        wrap: Wrap,
+        #[serde(default)]
+        linear: bool,
+    },
+    Checker {
+        even: (f64, f64, f64),
+        odd: (f64, f64, f64),
+        #[serde(default = "default_scale")]
+        scale: f64,
+    },
+    Noise {
+        #[serde(default)]
+        color0: (f64, f64, f64),
+        #[serde(default = "default_color1")]
+        color1: (f64, f64, f64),
+        #[serde(default = "default_scale")]
+        scale: f64,
+        #[serde(default = "default_octaves")]
+        octaves: u32,
+        #[serde(default)]
+        seed: u64,
+    },
+}
+
+impl SerializationTexture {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // an image whose colors are decoded from sRGB, which would bend data such as normals
+    pub fn is_srgb(&self) -> bool {
+        matches!(
+            self.kind,
+            SerializationTextureKind::Image { linear: false, .. }
+        )
+    }
+
+    // image paths are written relative to the scene file
+    pub fn resolve_path(&mut self, scene_dir: &Path) {
+        if let SerializationTextureKind::Image { path, .. } = &mut self.kind {
+            *path = scene_dir.join(&*path);
+        }
+    }
+}
+
+fn check_scale(scale: f64) -> Result<(), FieldError> {
+    if scale.is_finite() && scale > 0.0 {
+        Ok(())
+    } else {
+        Err(FieldError::new("scale", "scale must be positive"))
+    }
+}
+
+impl Validate for SerializationTexture {
+    fn validate(&self) -> Result<(), FieldError> {
+        match self.kind {
+            SerializationTextureKind::Image { .. } => Ok(()),
+            SerializationTextureKind::Checker { even, odd, scale } => {
+                check_color("even", even)?;
+                check_color("odd", odd)?;
+                check_scale(scale)
+            }
+            SerializationTextureKind::Noise {
+                color0,
+                color1,
+                scale,
+                octaves,
+                ..
+            } => {
+                check_color("color0", color0)?;
+                check_color("color1", color1)?;
+                check_scale(scale)?;
+                if !(1..=16).contains(&octaves) {
+                    return Err(FieldError::new("octaves", "must be between 1 and 16"));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl TryFrom<&SerializationTexture> for Arc<dyn Texture> {
+    type Error = RaytError;
+
+    fn try_from(value: &SerializationTexture) -> Result<Self, Self::Error> {
+        Ok(match &value.kind {
+            SerializationTextureKind::Image { path, wrap, linear } => {
+                Arc::new(ImageTexture::load(path, *wrap, *linear)?)
+            }
+            SerializationTextureKind::Checker { even, odd, scale } => {
+                Arc::new(Checker::new((*even).into(), (*odd).into(), *scale))
+            }
+            SerializationTextureKind::Noise {
+                color0,
+                color1,
+                scale,
+                octaves,
+                seed,
+            } => Arc::new(Noise::new(
+                (*color0).into(),
+                (*color1).into(),
+                *scale,
+                *octaves,
+                *seed,
+            )),
+        })
+    }
+}
+
+pub struct TextureLibrary {
+    textures: HashMap<String, Arc<dyn Texture>>,
+}
+
+impl TextureLibrary {
+    pub fn new<'a>(
+        textures: impl IntoIterator<Item = &'a SerializationTexture>,
+    ) -> Result<Self, RaytError> {
+        Ok(Self {
+            textures: textures
+                .into_iter()
+                .map(|texture| Ok((texture.name.clone(), texture.try_into()?)))
+                .collect::<Result<_, RaytError>>()?,
+        })
+    }
+
+    // names are checked against the library when the scene is validated
+    pub fn get(&self, name: &str) -> Arc<dyn Texture> {
+        self.textures
+            .get(name)
+            .unwrap_or_else(|| panic!("unknown texture `{}`", name))
+            .clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Checker, ImageTexture, Noise, Perlin, Texture, Wrap};
+    use crate::maths::{Color, Vec3};
+
+    #[test]
+    fn image_lookups_filter_and_wrap() {
+        let black = Color::new(0.0, 0.0, 0.0);
+        let white = Color::new(1.0, 1.0, 1.0);
+        // left column black, right column white
+        let pixels = vec![black, white, black, white];
+        let origin = Vec3::new(0.0, 0.0, 0.0);
+        let value = |wrap, u: f64| {
+            ImageTexture::new(2, 2, pixels.clone(), wrap)
+                .value((u, 0.5), origin)
+                .x
+        };
+
+        // pixel centers and halfway between them
+        assert!((value(Wrap::Clamp, 0.25) - 0.0).abs() < 1e-12);
+        assert!((value(Wrap::Clamp, 0.75) - 1.0).abs() < 1e-12);
+        assert!((value(Wrap::Clamp, 0.5) - 0.5).abs() < 1e-12);
+        // past the right edge repeating blends back towards the black column
+        assert!((value(Wrap::Clamp, 1.0) - 1.0).abs() < 1e-12);
+        assert!((value(Wrap::Repeat, 1.0) - 0.5).abs() < 1e-12);
+        assert!((value(Wrap::Mirror, 1.0) - 1.0).abs() < 1e-12);
+        assert!((value(Wrap::Repeat, 1.25) - 0.0).abs() < 1e-12);
+        assert!((value(Wrap::Mirror, 1.25) - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn noise_seed_changes_the_pattern() {
+        let noise = |seed| {
+            let white = Color::new(1.0, 1.0, 1.0);
+            Noise::new(Color::new(0.0, 0.0, 0.0), white, 1.0, 1, seed)
+        };
+        let (a, b, c) = (noise(0), noise(0), noise(1));
+        let mut differs = false;
+        for i in 0..100 {
+            let p = Vec3::new(i as f64 * 0.37, 0.5, i as f64 * 0.11);
+            assert_eq!(a.value((0.0, 0.0), p).x, b.value((0.0, 0.0), p).x);
+            differs |= a.value((0.0, 0.0), p).x != c.value((0.0, 0.0), p).x;
+        }
+        assert!(differs);
+    }
+
+    #[test]
+    fn procedural_textures_stay_in_range() {
+        let perlin = Perlin::new(0);
+        let checker = Checker::new(Color::new(0.0, 0.0, 0.0), Color::new(1.0, 1.0, 1.0), 2.0);
+        let mut values = vec![];
+        for i in 0..1000 {
+            let t = i as f64 * 0.137;
+            let p = Vec3::new(t.sin() * 7.3, t * 0.71, (t * 1.3).cos() * 5.1 - t);
+            let n = perlin.fbm(p, 4);
+            assert!((-1.0..=1.0).contains(&n));
+            values.push(n);
+            // noise vanishes on the lattice
+            let lattice = Vec3::new(p.x.floor(), p.y.floor(), p.z.floor());
+            assert!(perlin.noise(lattice).abs() < 1e-12);
+
+            let c = checker.value((0.0, 0.0), p).x;
+            let shifted = checker.value((0.0, 0.0), p + Vec3::new(0.5, 0.0, 0.0)).x;
+            assert!(c == 0.0 || c == 1.0);
+            assert_ne!(c, shifted);
+        }
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        assert!(mean.abs() < 0.1);
+        assert!(values.iter().any(|n| n.abs() > 0.2));
+    }
+}
@@ -0,0 +1,196 @@
+use serde::Deserialize;
+
+use crate::{
+    error::{FieldError, Validate},
+    maths::Color,
+};
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+pub enum ToneMapper {
+    #[default]
+    Clamp,
+    Reinhard,
+    ExtendedReinhard,
+    #[serde(rename = "ACES")]
+    Aces,
+    AgX,
+}
+
+// turns linear scene radiance into sRGB encoded values in [0, 1]
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct DisplayTransform {
+    pub tone_mapper: ToneMapper,
+    // in stops, every +1 doubles the radiance before tone mapping
+    pub exposure: f64,
+    // smallest radiance mapped to pure white by the extended Reinhard operator
+    pub white_point: f64,
+}
+
+impl Default for DisplayTransform {
+    fn default() -> Self {
+        Self {
+            tone_mapper: ToneMapper::Clamp,
+            exposure: 0.0,
+            white_point: 4.0,
+        }
+    }
+}
+
+impl Validate for DisplayTransform {
+    fn validate(&self) -> Result<(), FieldError> {
+        if !self.exposure.is_finite() {
+            return Err(FieldError::new(
+                "exposure",
+                "exposure must be a finite number",
+            ));
+        }
+        if !(self.white_point.is_finite() && self.white_point > 0.0) {
+            return Err(FieldError::new(
+                "white_point",
+                "white point must be positive",
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl DisplayTransform {
+    pub fn apply(&self, radiance: Color) -> Color {
+        let color = (radiance * 2f64.powf(self.exposure)).max(&Color::new(0.0, 0.0, 0.0));
+        let mapped = match self.tone_mapper {
+            ToneMapper::Clamp => color,
+            ToneMapper::Reinhard => reinhard(color, f64::INFINITY),
+            ToneMapper::ExtendedReinhard => reinhard(color, self.white_point),
+            ToneMapper::Aces => aces(color),
+            ToneMapper::AgX => agx(color),
+        };
+        let mapped = mapped.clamp(0.0, 1.0);
+        Color::new(
+            srgb_oetf(mapped.x),
+            srgb_oetf(mapped.y),
+            srgb_oetf(mapped.z),
+        )
+    }
+}
+
+pub fn srgb_oetf(c: f64) -> f64 {
+    if c <= 0.0031308 {
+        12.92 * c
+    } else {
+        1.055 * c.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+fn mul(m: &[[f64; 3]; 3], c: Color) -> Color {
+    Color::new(
+        m[0][0] * c.x + m[0][1] * c.y + m[0][2] * c.z,
+        m[1][0] * c.x + m[1][1] * c.y + m[1][2] * c.z,
+        m[2][0] * c.x + m[2][1] * c.y + m[2][2] * c.z,
+    )
+}
+
+// Reinhard on luminance so saturated colors keep their hue, white = infinity gives the
+// basic L / (1 + L) curve
+fn reinhard(color: Color, white: f64) -> Color {
+    let l = color.luminance();
+    if l <= 0.0 {
+        return color;
+    }
+    let mapped = l * (1.0 + l / (white * white)) / (1.0 + l);
+    color * (mapped / l)
+}
+
+// Stephen Hill's fit of the ACES reference rendering and output transforms
+fn aces(color: Color) -> Color {
+    const INPUT: [[f64; 3]; 3] = [
+        [0.59719, 0.35458, 0.04823],
+        [0.07600, 0.90834, 0.01566],
+        [0.02840, 0.13383, 0.83777],
+    ];
+    const OUTPUT: [[f64; 3]; 3] = [
+        [1.60475, -0.53108, -0.07367],
+        [-0.10208, 1.10813, -0.00605],
+        [-0.00327, -0.07276, 1.07602],
+    ];
+    let rrt_odt =
+        |v: f64| (v * (v + 0.0245786) - 0.000090537) / (v * (0.983729 * v + 0.4329510) + 0.238081);
+
+    let c = mul(&INPUT, color);
+    mul(
+        &OUTPUT,
+        Color::new(rrt_odt(c.x), rrt_odt(c.y), rrt_odt(c.z)),
+    )
+}
+
+// AgX base look: a log2 encoding in an inset gamut followed by a sigmoid, the polynomial
+// approximation of the curve is the one from the minimal GLSL implementation
+fn agx(color: Color) -> Color {
+    const INSET: [[f64; 3]; 3] = [
+        [0.842479062253094, 0.0784335999999992, 0.0792237451477643],
+        [0.0423282422610123, 0.878468636469772, 0.0791661274605434],
+        [0.0423756549057051, 0.0784336, 0.879142973793104],
+    ];
+    const OUTSET: [[f64; 3]; 3] = [
+        [1.19687900512017, -0.0980208811401368, -0.0990297440797205],
+        [-0.0528968517574562, 1.15190312990417, -0.0989611768448433],
+        [-0.0529716355144438, -0.0980434501171241, 1.15107367264116],
+    ];
+    const MIN_EV: f64 = -12.47393;
+    const MAX_EV: f64 = 4.026069;
+
+    let curve = |v: f64| {
+        let x = (v.max(1e-10).log2().clamp(MIN_EV, MAX_EV) - MIN_EV) / (MAX_EV - MIN_EV);
+        let x2 = x * x;
+        let x4 = x2 * x2;
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.00232
+    };
+
+    let c = mul(&INSET, color);
+    let c = mul(&OUTSET, Color::new(curve(c.x), curve(c.y), curve(c.z)));
+    // the curve ends in display space, go back to linear so every mapper shares the sRGB encoding
+    Color::new(
+        c.x.max(0.0).powf(2.2),
+        c.y.max(0.0).powf(2.2),
+        c.z.max(0.0).powf(2.2),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{srgb_oetf, DisplayTransform, ToneMapper};
+    use crate::maths::Color;
+
+    #[test]
+    fn tone_mappers_are_monotonic_and_bounded() {
+        assert_eq!(srgb_oetf(0.0), 0.0);
+        assert!((srgb_oetf(1.0) - 1.0).abs() < 1e-12);
+
+        for tone_mapper in [
+            ToneMapper::Clamp,
+            ToneMapper::Reinhard,
+            ToneMapper::ExtendedReinhard,
+            ToneMapper::Aces,
+            ToneMapper::AgX,
+        ] {
+            let display = DisplayTransform {
+                tone_mapper,
+                ..DisplayTransform::default()
+            };
+            let mut last = -1.0;
+            for i in 0..200 {
+                let v = i as f64 * 0.1;
+                let mapped = display.apply(Color::new(v, v, v));
+                assert!(
+                    (0.0..=1.0).contains(&mapped.y),
+                    "{:?} at {}",
+                    tone_mapper,
+                    v
+                );
+                assert!(mapped.y >= last - 1e-9, "{:?} at {}", tone_mapper, v);
+                last = mapped.y;
+            }
+        }
+    }
+}